@@ -0,0 +1,251 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::errors::AuthError;
+use crate::mechanism::AuthMechanism;
+use crate::responses::CapaResponse;
+use crate::stream::Security;
+
+/// The authentication method that was used to log in to the POP3 server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// The USER and PASS commands
+    UserPass,
+    /// The APOP command, which sends a digest of the greeting timestamp and the password
+    Apop,
+    /// SASL CRAM-MD5, which sends an HMAC-MD5 of a server challenge, keyed with the password
+    CramMd5,
+    /// SASL PLAIN, which sends the username and password in a single base64 message
+    SaslPlain,
+    /// SASL XOAUTH2, which sends an OAuth 2.0 access token instead of a password, as Gmail and Outlook require
+    XOAuth2,
+    /// An `AuthMechanism` of your own, passed to `auth` on the builder
+    Custom,
+}
+
+/// The name of the method in the protocol, like `CRAM-MD5`
+impl Display for AuthMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthMethod::UserPass => f.write_str("USER/PASS"),
+            AuthMethod::Apop => f.write_str("APOP"),
+            AuthMethod::CramMd5 => f.write_str("CRAM-MD5"),
+            AuthMethod::SaslPlain => f.write_str("PLAIN"),
+            AuthMethod::XOAuth2 => f.write_str("XOAUTH2"),
+            AuthMethod::Custom => f.write_str("a custom mechanism"),
+        }
+    }
+}
+
+/// The hash function APOP uses to compute the digest of the timestamp and the secret
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApopDigest {
+    /// MD5, as specified by RFC 1939 and supported by every server that supports APOP
+    #[default]
+    Md5,
+    /// SHA-1, for the few servers that support it
+    Sha1,
+    /// SHA-256, for the few servers that support it
+    Sha256,
+}
+
+/// How the builder was told to log in
+#[derive(Clone)]
+pub(crate) enum AuthChoice {
+    Method(AuthMethod),
+    Auto,
+    Custom(Arc<dyn AuthMechanism>),
+}
+
+impl AuthChoice {
+    /// The method the login will use, or None when it depends on the server
+    pub(crate) fn method(&self) -> Option<AuthMethod> {
+        match self {
+            AuthChoice::Method(method) => Some(*method),
+            AuthChoice::Auto => None,
+            AuthChoice::Custom(mechanism) => Some(mechanism.method()),
+        }
+    }
+}
+
+/// Pick the strongest method that both the server and this client support. PLAIN and USER/PASS send the password as is,
+/// so they are not picked over a plaintext connection. None is a stream set up by the caller, which we can't check.
+/// XOAUTH2 needs an access token instead of a password, so it is never picked
+pub(crate) fn select_auth_method(greeting: &str, capabilities: Option<&CapaResponse>, security: Option<Security>) -> Result<AuthMethod, AuthError> {
+    let method = if supports_sasl(capabilities, "CRAM-MD5") {
+        AuthMethod::CramMd5
+    } else if apop_timestamp(greeting).is_some() {
+        AuthMethod::Apop
+    } else if supports_sasl(capabilities, "PLAIN") {
+        AuthMethod::SaslPlain
+    } else {
+        AuthMethod::UserPass
+    };
+    match method {
+        AuthMethod::SaslPlain | AuthMethod::UserPass if security == Some(Security::Plaintext) => Err(format!(
+            "the server only supports sending the password as is ({method}), which is not chosen automatically over a plaintext connection"
+        ).into()),
+        method => Ok(method),
+    }
+}
+
+/// Check whether the server advertised the given mechanism in its SASL capability
+pub(crate) fn supports_sasl(capabilities: Option<&CapaResponse>, mechanism: &str) -> bool {
+    capabilities
+        .and_then(|c| c.arguments("SASL"))
+        .unwrap_or_default()
+        .iter()
+        .any(|m| m.eq_ignore_ascii_case(mechanism))
+}
+
+/// Servers that support APOP include a timestamp like `<1896.697170952@dbc.mtview.ca.us>` in their greeting
+pub(crate) fn apop_timestamp(greeting: &str) -> Option<&str> {
+    let start = greeting.find('<')?;
+    let end = greeting[start..].find('>')? + start;
+    Some(&greeting[start..=end])
+}
+
+pub(crate) fn apop_digest(timestamp: &str, secret: &str, digest: ApopDigest) -> String {
+    let input = format!("{timestamp}{secret}");
+    match digest {
+        ApopDigest::Md5 => to_hex(&Md5::digest(input.as_bytes())),
+        ApopDigest::Sha1 => to_hex(&Sha1::digest(input.as_bytes())),
+        ApopDigest::Sha256 => to_hex(&Sha256::digest(input.as_bytes())),
+    }
+}
+
+pub(crate) fn cram_md5_response(user: &str, secret: &str, challenge: &str) -> Result<String, String> {
+    let challenge = STANDARD.decode(challenge)
+        .map_err(|err| format!("invalid CRAM-MD5 challenge: {err}"))?;
+    let mut mac = Hmac::<Md5>::new_from_slice(secret.as_bytes())
+        .map_err(|err| err.to_string())?;
+    mac.update(&challenge);
+    let digest = mac.finalize().into_bytes();
+    Ok(STANDARD.encode(format!("{user} {}", to_hex(&digest))))
+}
+
+pub(crate) fn sasl_plain_response(user: &str, pass: &str) -> String {
+    STANDARD.encode(format!("\0{user}\0{pass}"))
+}
+
+pub(crate) fn xoauth2_response(user: &str, token: &str) -> String {
+    STANDARD.encode(format!("user={user}\x01auth=Bearer {token}\x01\x01"))
+}
+
+/// SASL servers sometimes send their error as base64, in which case we decode it for a readable message
+pub(crate) fn decode_server_message(message: String) -> String {
+    STANDARD.decode(&message).ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .filter(|decoded| decoded.chars().all(|c| !c.is_control() || c.is_whitespace()))
+        .unwrap_or(message)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apop_timestamp_from_greeting() {
+        let actual = apop_timestamp("POP3 server ready <1896.697170952@dbc.mtview.ca.us>");
+
+        assert_eq!(actual, Some("<1896.697170952@dbc.mtview.ca.us>"));
+    }
+
+    #[test]
+    fn test_apop_timestamp_missing_from_greeting() {
+        assert_eq!(apop_timestamp("POP3 server ready"), None);
+    }
+
+    #[test]
+    fn test_apop_digest_matches_rfc_example() {
+        let actual = apop_digest("<1896.697170952@dbc.mtview.ca.us>", "tanstaaf", ApopDigest::Md5);
+
+        assert_eq!(actual, "c4c9334bac560ecc979e58001b3e22fb".to_string());
+    }
+
+    #[test]
+    fn test_apop_digest_with_sha() {
+        let timestamp = "<1896.697170952@dbc.mtview.ca.us>";
+
+        assert_eq!(apop_digest(timestamp, "tanstaaf", ApopDigest::Sha1), "f27a22ec281d6b972a033a820f787391efea6177".to_string());
+        assert_eq!(apop_digest(timestamp, "tanstaaf", ApopDigest::Sha256), "44242ad369466f39e2204f402942a86d0e2b316120deb53b3449512cecdd3bb1".to_string());
+    }
+
+    #[test]
+    fn test_cram_md5_response_matches_rfc_example() {
+        let challenge = STANDARD.encode("<1896.697170952@postoffice.reston.mci.net>");
+
+        let actual = cram_md5_response("tim", "tanstaaftanstaaf", &challenge).unwrap();
+
+        assert_eq!(actual, "dGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw".to_string());
+    }
+
+    #[test]
+    fn test_sasl_plain_response() {
+        let actual = sasl_plain_response("tim", "tanstaaf");
+
+        assert_eq!(actual, "AHRpbQB0YW5zdGFhZg==".to_string());
+    }
+
+    #[test]
+    fn test_decode_server_message() {
+        assert_eq!(decode_server_message("aW52YWxpZCBjcmVkZW50aWFscw==".to_string()), "invalid credentials".to_string());
+        assert_eq!(decode_server_message("authentication failed".to_string()), "authentication failed".to_string());
+    }
+
+    #[test]
+    fn test_auth_method_display_is_protocol_name() {
+        assert_eq!(AuthMethod::UserPass.to_string(), "USER/PASS".to_string());
+        assert_eq!(AuthMethod::CramMd5.to_string(), "CRAM-MD5".to_string());
+        assert_eq!(AuthMethod::XOAuth2.to_string(), "XOAUTH2".to_string());
+    }
+
+    #[test]
+    fn test_select_auth_method_prefers_apop_when_greeting_has_timestamp() {
+        assert_eq!(select_auth_method("ready <1.2@host>", None, None).unwrap(), AuthMethod::Apop);
+        assert_eq!(select_auth_method("ready", None, None).unwrap(), AuthMethod::UserPass);
+    }
+
+    #[test]
+    fn test_select_auth_method_prefers_cram_md5_when_advertised() {
+        let capabilities: CapaResponse = "SASL PLAIN CRAM-MD5".to_string().try_into().unwrap();
+
+        assert_eq!(select_auth_method("ready <1.2@host>", Some(&capabilities), None).unwrap(), AuthMethod::CramMd5);
+    }
+
+    #[test]
+    fn test_select_auth_method_prefers_apop_over_plain() {
+        let capabilities: CapaResponse = "SASL PLAIN".to_string().try_into().unwrap();
+
+        assert_eq!(select_auth_method("ready <1.2@host>", Some(&capabilities), None).unwrap(), AuthMethod::Apop);
+        assert_eq!(select_auth_method("ready", Some(&capabilities), None).unwrap(), AuthMethod::SaslPlain);
+    }
+
+    #[test]
+    fn test_select_auth_method_refuses_plain_password_over_plaintext() {
+        let capabilities: CapaResponse = "SASL PLAIN".to_string().try_into().unwrap();
+
+        assert!(select_auth_method("ready", Some(&capabilities), Some(Security::Plaintext)).is_err());
+        assert!(select_auth_method("ready", None, Some(Security::Plaintext)).is_err());
+        assert_eq!(select_auth_method("ready <1.2@host>", None, Some(Security::Plaintext)).unwrap(), AuthMethod::Apop);
+        assert_eq!(select_auth_method("ready", Some(&capabilities), Some(Security::StartTls)).unwrap(), AuthMethod::SaslPlain);
+    }
+
+    #[test]
+    fn test_xoauth2_response() {
+        let actual = xoauth2_response("someuser@example.com", "ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg");
+
+        assert_eq!(actual, "dXNlcj1zb21ldXNlckBleGFtcGxlLmNvbQFhdXRoPUJlYXJlciB5YTI5LnZGOWRmdDRxbVRjMk52YjNSbGNrQmhkSFJoZG1semRHRXVZMjl0Q2cBAQ==".to_string());
+    }
+}
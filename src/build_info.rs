@@ -0,0 +1,34 @@
+/// BuildInfo is the version of this crate and the optional features it was compiled with, e.g. for bug reports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+/// The version and the enabled features of this crate
+pub fn build_info() -> BuildInfo {
+    let features = [
+        ("webpki-roots", cfg!(feature = "webpki-roots")),
+        ("rustls-platform-verifier", cfg!(feature = "rustls-platform-verifier")),
+        ("native-tls", cfg!(feature = "native-tls")),
+        ("time", cfg!(feature = "time")),
+    ];
+
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        features: features.into_iter().filter(|(_, enabled)| *enabled).map(|(name, _)| name).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let actual = build_info();
+
+        assert_eq!(actual.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(actual.features.contains(&"time"), cfg!(feature = "time"));
+    }
+}
@@ -1,11 +1,65 @@
 use rustls::{ClientConfig, RootCertStore};
 
-pub fn create_rustls_config() -> Result<ClientConfig, String> {
+/// Where the root certificates for verifying the server come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RootCertificates {
+    /// The trust store of the operating system. With the `webpki-roots` feature, the bundled Mozilla
+    /// root certificates are used when the operating system has none (e.g. minimal containers)
+    #[default]
+    Native,
+    /// The bundled Mozilla root certificates
+    #[cfg(feature = "webpki-roots")]
+    WebPki,
+    /// The certificate verifier of the platform, which also checks revocation and picks up roots installed by
+    /// enterprise policies on macOS and Windows
+    #[cfg(feature = "rustls-platform-verifier")]
+    Platform,
+}
+
+pub fn create_rustls_config(root_certificates: RootCertificates) -> Result<ClientConfig, String> {
+    let root_store = match root_certificates {
+        // the platform verifier does not use a root store
+        #[cfg(feature = "rustls-platform-verifier")]
+        RootCertificates::Platform => return Ok(rustls_platform_verifier::tls_config()),
+        RootCertificates::Native => native_root_store()?,
+        #[cfg(feature = "webpki-roots")]
+        RootCertificates::WebPki => webpki_root_store(),
+    };
+    // minimal containers often have no certificates installed
+    #[cfg(feature = "webpki-roots")]
+    let root_store = if root_store.is_empty() { webpki_root_store() } else { root_store };
+    // without roots every handshake fails with an opaque verification error, so fail early with some advice
+    if root_store.is_empty() {
+        return Err("no system root certificates found; install ca-certificates or enable webpki-roots".to_string());
+    }
+    Ok(rustls_config_with_roots(root_store))
+}
+
+/// A config with exactly the given root certificates, without falling back to others
+pub fn create_rustls_config_with_store(root_store: RootCertStore) -> Result<ClientConfig, String> {
+    if root_store.is_empty() {
+        return Err("the root store passed to root_store is empty".to_string());
+    }
+    Ok(rustls_config_with_roots(root_store))
+}
+
+fn rustls_config_with_roots(root_store: RootCertStore) -> ClientConfig {
+    ClientConfig::builder()
+        .with_root_certificates(root_store)
+        .with_no_client_auth()
+}
+
+fn native_root_store() -> Result<RootCertStore, String> {
     let mut root_store = RootCertStore::empty();
     for cert in rustls_native_certs::load_native_certs().map_err(|err| err.to_string())? {
         root_store.add(cert).map_err(|err| err.to_string())?;
     }
-    Ok(ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth())
+    Ok(root_store)
 }
+
+#[cfg(feature = "webpki-roots")]
+fn webpki_root_store() -> RootCertStore {
+    let mut root_store = RootCertStore::empty();
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    root_store
+}
\ No newline at end of file
@@ -0,0 +1,143 @@
+use time::{Date, Month, OffsetDateTime, Time, UtcOffset};
+
+/// Parse an RFC 5322 date, like `Tue, 1 Jul 2003 10:52:37 +0200`.
+/// Also accepts the obsolete syntax of section 4.3 (two-digit years, zone names like `PST`) and comments like `(UTC)`
+pub(crate) fn parse_rfc5322_date(value: &str) -> Option<OffsetDateTime> {
+    let without_comments = strip_comments(value).replace(',', " ");
+    let mut tokens = without_comments.split_whitespace().peekable();
+
+    // the day of the week is optional, and redundant
+    if tokens.peek()?.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        tokens.next();
+    }
+
+    let day: u8 = tokens.next()?.parse().ok()?;
+    let month = parse_month(tokens.next()?)?;
+    let year = parse_year(tokens.next()?)?;
+    let time = parse_time(tokens.next()?)?;
+    // a missing zone is not allowed, but we treat it, like an unknown zone name, as UTC. A malformed numeric zone is invalid
+    let offset = match tokens.next() {
+        Some(zone) => parse_zone(zone)?,
+        None => UtcOffset::UTC,
+    };
+
+    let date = Date::from_calendar_date(year, month, day).ok()?;
+    Some(date.with_time(time).assume_offset(offset))
+}
+
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0;
+    value.chars()
+        .filter(|c| match c {
+            '(' => {
+                depth += 1;
+                false
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                false
+            }
+            _ => depth == 0,
+        })
+        .collect()
+}
+
+fn parse_month(value: &str) -> Option<Month> {
+    const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    let prefix = value.get(..3)?.to_ascii_lowercase();
+    let index = MONTHS.iter().position(|month| *month == prefix)?;
+    Month::try_from(index as u8 + 1).ok()
+}
+
+fn parse_year(value: &str) -> Option<i32> {
+    let year: i32 = value.parse().ok()?;
+    // section 4.3: two-digit years below 50 are in the 2000s, other two- and three-digit years count from 1900
+    match (value.len(), year) {
+        (1..=2, 0..=49) => Some(year + 2000),
+        (1..=3, _) => Some(year + 1900),
+        _ => Some(year),
+    }
+}
+
+fn parse_time(value: &str) -> Option<Time> {
+    let mut parts = value.split(':');
+    let hour = parts.next()?.parse().ok()?;
+    let minute = parts.next()?.parse().ok()?;
+    let second: u8 = parts.next().map(|second| second.parse()).transpose().ok()?.unwrap_or(0);
+    // leap seconds can't be represented, so we use the second before
+    Time::from_hms(hour, minute, second.min(59)).ok()
+}
+
+fn parse_zone(value: &str) -> Option<UtcOffset> {
+    let (sign, digits) = match value.as_bytes().first()? {
+        b'+' => (1, &value[1..]),
+        b'-' => (-1, &value[1..]),
+        _ => return Some(obsolete_zone(value)),
+    };
+    let digits = digits.replace(':', "");
+    // checking the bytes before slicing, as slicing non-ASCII input could split a character
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i8 = digits[..2].parse().ok()?;
+    let minutes: i8 = digits[2..].parse().ok()?;
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+// section 4.3: the US zones have a known offset, other (military) zones should be treated as UTC
+fn obsolete_zone(value: &str) -> UtcOffset {
+    let hours = match value.to_ascii_uppercase().as_str() {
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        _ => 0,
+    };
+    UtcOffset::from_hms(hours, 0, 0).unwrap_or(UtcOffset::UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_parse_rfc5322_date() {
+        let actual = parse_rfc5322_date("Tue, 1 Jul 2003 10:52:37 +0200").unwrap();
+
+        assert_eq!(actual, datetime!(2003-07-01 10:52:37 +02:00));
+    }
+
+    #[test]
+    fn test_parse_rfc5322_date_with_comment_and_obsolete_zone() {
+        let actual = parse_rfc5322_date("Fri, 21 Nov 1997 09:55:06 PST (Pacific Standard Time)").unwrap();
+
+        assert_eq!(actual, datetime!(1997-11-21 09:55:06 -08:00));
+    }
+
+    #[test]
+    fn test_parse_rfc5322_date_without_day_of_week_and_seconds() {
+        let actual = parse_rfc5322_date("3 Jan 2022 9:05 -0500 (EST)").unwrap();
+
+        assert_eq!(actual, datetime!(2022-01-03 09:05:00 -05:00));
+    }
+
+    #[test]
+    fn test_parse_rfc5322_date_with_two_digit_year() {
+        assert_eq!(parse_rfc5322_date("Thu, 13 Feb 69 23:32:54 GMT").unwrap(), datetime!(1969-02-13 23:32:54 UTC));
+        assert_eq!(parse_rfc5322_date("Thu, 13 Feb 03 23:32:54 GMT").unwrap(), datetime!(2003-02-13 23:32:54 UTC));
+    }
+
+    #[test]
+    fn test_parse_rfc5322_date_invalid() {
+        assert_eq!(parse_rfc5322_date("yesterday"), None);
+        assert_eq!(parse_rfc5322_date("31 Feb 2020 10:00:00 +0000"), None);
+    }
+
+    #[test]
+    fn test_parse_rfc5322_date_with_malformed_numeric_zone() {
+        assert_eq!(parse_rfc5322_date("Tue, 1 Jul 2003 10:52:37 +0é0"), None);
+        assert_eq!(parse_rfc5322_date("Tue, 1 Jul 2003 10:52:37 +02"), None);
+    }
+}
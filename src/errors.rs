@@ -2,9 +2,13 @@ use core::fmt::Formatter;
 use std::error::Error;
 use std::fmt::{Debug, Display};
 use std::num::ParseIntError;
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
 
 use rustls::pki_types::InvalidDnsNameError;
 
+use crate::reader::{is_transport_failure, response_code, ReadError, ResponseError, REFUSED_GREETING_START};
+
 // helpers //
 
 macro_rules! impl_err {
@@ -12,6 +16,8 @@ macro_rules! impl_err {
         #[derive(Debug)]
         pub struct $err {
             pub message: String,
+            /// The RFC 2449 response code of the server, like `SYS/PERM` for `-ERR [SYS/PERM] account disabled`
+            pub code: Option<String>,
         }
 
         impl Display for $err {
@@ -23,11 +29,41 @@ macro_rules! impl_err {
     }
 }
 
+macro_rules! impl_err_with_kind {
+    ($err:ident, $kind:ident) => {
+        #[derive(Debug)]
+        pub struct $err {
+            pub message: String,
+            /// The RFC 2449 response code of the server, like `SYS/PERM` for `-ERR [SYS/PERM] account disabled`
+            pub code: Option<String>,
+            pub kind: $kind,
+        }
+
+        impl Display for $err {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.message)
+            }
+        }
+        impl Error for $err {}
+
+        impl From<String> for $err {
+            fn from(message: String) -> Self {
+                $err {
+                    code: response_code(&message),
+                    message,
+                    kind: $kind::Other,
+                }
+            }
+        }
+    }
+}
+
 macro_rules! implement_from_string {
     ($err:ident) => {
         impl From<String> for $err {
             fn from(message: String) -> Self {
                 $err {
+                    code: response_code(&message),
                     message,
                 }
             }
@@ -57,6 +93,7 @@ macro_rules! implement_pop3_from {
 #[derive(Debug)]
 pub enum Pop3Error {
     ConnectionError(ConnectionError),
+    AuthError(AuthError),
     StatError(StatError),
     ListError(ListError),
     RetrieveError(RetrieveError),
@@ -65,12 +102,14 @@ pub enum Pop3Error {
     NoopError(NoopError),
     TopError(TopError),
     UIDLError(UIDLError),
+    CapaError(CapaError),
 }
 
 impl Display for Pop3Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Pop3Error::ConnectionError(err) => f.write_str(&format!("ConnectionError: {}", err.message)),
+            Pop3Error::AuthError(err) => f.write_str(&format!("AuthError: {}", err.message)),
             Pop3Error::StatError(err) => f.write_str(&format!("StatError: {}", err.message)),
             Pop3Error::ListError(err) => f.write_str(&format!("ListError: {}", err.message)),
             Pop3Error::RetrieveError(err) => f.write_str(&format!("RetrieveError: {}", err.message)),
@@ -79,13 +118,31 @@ impl Display for Pop3Error {
             Pop3Error::NoopError(err) => f.write_str(&format!("NoopError: {}", err.message)),
             Pop3Error::TopError(err) => f.write_str(&format!("TopError: {}", err.message)),
             Pop3Error::UIDLError(err) => f.write_str(&format!("UIDLError: {}", err.message)),
+            Pop3Error::CapaError(err) => f.write_str(&format!("CapaError: {}", err.message)),
         }
     }
 }
 
-impl Error for Pop3Error {}
+impl Error for Pop3Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Pop3Error::ConnectionError(err) => Some(err),
+            Pop3Error::AuthError(err) => Some(err),
+            Pop3Error::StatError(err) => Some(err),
+            Pop3Error::ListError(err) => Some(err),
+            Pop3Error::RetrieveError(err) => Some(err),
+            Pop3Error::DeleteError(err) => Some(err),
+            Pop3Error::ResetError(err) => Some(err),
+            Pop3Error::NoopError(err) => Some(err),
+            Pop3Error::TopError(err) => Some(err),
+            Pop3Error::UIDLError(err) => Some(err),
+            Pop3Error::CapaError(err) => Some(err),
+        }
+    }
+}
 
 implement_pop3_from!(ConnectionError);
+implement_pop3_from!(AuthError);
 implement_pop3_from!(StatError);
 implement_pop3_from!(ListError);
 implement_pop3_from!(RetrieveError);
@@ -94,31 +151,122 @@ implement_pop3_from!(ResetError);
 implement_pop3_from!(NoopError);
 implement_pop3_from!(TopError);
 implement_pop3_from!(UIDLError);
+implement_pop3_from!(CapaError);
 
 // specific errors //
 
-impl_err_with_from_str!(ConnectionError);
+impl_err_with_kind!(ConnectionError, ConnectionErrorKind);
+
+/// The kind of ConnectionError, so you can, for example, retry later when the mailbox is locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionErrorKind {
+    /// Another session is using the mailbox (RFC 2449 `[IN-USE]`)
+    MailboxLocked,
+    /// The server sent an `-ERR` greeting, so no session could be started (for example because the service is not available)
+    ServerRefused,
+    /// The host could not be resolved (in time), as opposed to the server refusing or not answering the connection
+    DnsResolution,
+    /// The connection was refused, reset, closed or timed out, which (unlike a certificate or configuration problem) may well work on a retry
+    Transport,
+    Other,
+}
+
+// servers without response codes describe errors in words, so match whole words ("account blocked" is not locked)
+fn contains_words(message: &str, phrase: &[&str]) -> bool {
+    let lowercase = message.to_lowercase();
+    let words: Vec<&str> = lowercase.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).collect();
+    words.windows(phrase.len()).any(|window| window == phrase)
+}
+
+fn mentions_lock(message: &str) -> bool {
+    contains_words(message, &["locked"]) || contains_words(message, &["in", "use"])
+}
+
+impl ConnectionError {
+    pub(crate) fn from_server_message(message: String) -> Self {
+        let code = response_code(&message);
+        let kind = if code.as_deref() == Some("IN-USE") || mentions_lock(&message) {
+            ConnectionErrorKind::MailboxLocked
+        } else {
+            ConnectionErrorKind::Other
+        };
+        ConnectionError {
+            message,
+            code,
+            kind,
+        }
+    }
+
+    pub(crate) fn from_greeting(error: ResponseError) -> Self {
+        let message = match error {
+            ResponseError::Server(message) => message,
+            error => return ConnectionError::from_missing_response(error),
+        };
+        match message.strip_prefix(REFUSED_GREETING_START) {
+            Some(server_message) => ConnectionError {
+                code: response_code(server_message),
+                message,
+                kind: ConnectionErrorKind::ServerRefused,
+            },
+            None => message.into(),
+        }
+    }
+
+    /// For a response that did not arrive, where only a broken connection is a `Transport` error
+    pub(crate) fn from_missing_response(error: ResponseError) -> Self {
+        match error {
+            ResponseError::Transport(message) => ConnectionError {
+                message,
+                code: None,
+                kind: ConnectionErrorKind::Transport,
+            },
+            error => String::from(error).into(),
+        }
+    }
+}
 
 impl From<std::io::Error> for ConnectionError {
     fn from(value: std::io::Error) -> Self {
-        ConnectionError {
-            message: format!("could not set up client connection: {}", value.to_string()),
+        let message = format!("could not set up client connection: {}", value);
+        if is_transport_failure(value.kind()) {
+            ConnectionError::from_missing_response(ResponseError::Transport(message))
+        } else {
+            message.into()
         }
     }
 }
 
 impl From<rustls::Error> for ConnectionError {
     fn from(value: rustls::Error) -> Self {
-        ConnectionError {
-            message: format!("could not set up client connection: {}", value.to_string()),
-        }
+        format!("could not set up client connection: {}", value).into()
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl From<native_tls::Error> for ConnectionError {
+    fn from(value: native_tls::Error) -> Self {
+        format!("could not set up client connection: {}", value).into()
     }
 }
 
 impl From<InvalidDnsNameError> for ConnectionError {
     fn from(value: InvalidDnsNameError) -> Self {
-        ConnectionError {
-            message: format!("invalid host: {}", value.to_string()),
+        format!("invalid host: {}", value).into()
+    }
+}
+
+impl_err_with_from_str!(AuthError);
+
+impl Pop3Error {
+    /// A rejected login is an AuthError, except when the mailbox is locked, because then the same credentials will work later
+    pub(crate) fn from_login_message(message: String) -> Self {
+        let err = ConnectionError::from_server_message(message);
+        match err.kind {
+            ConnectionErrorKind::MailboxLocked => Pop3Error::ConnectionError(err),
+            _ => Pop3Error::AuthError(AuthError {
+                message: err.message,
+                code: err.code,
+            }),
         }
     }
 }
@@ -128,27 +276,102 @@ impl_err_with_from_str!(StatError);
 impl From<ParseIntError> for StatError {
     fn from(value: ParseIntError) -> Self {
         StatError {
-            message: format!("could not parse stat response as numbers: {}", value.to_string()),
+            message: format!("could not parse stat response as numbers: {}", value),
+            code: None,
         }
     }
 }
 
-impl_err_with_from_str!(ListError);
+impl_err_with_kind!(ListError, ListErrorKind);
+
+/// The kind of ListError, so you can tell a message that does not exist from one that was deleted in this session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListErrorKind {
+    /// The message was marked as deleted in this session, so the server no longer lists it (until RSET)
+    MessageDeleted,
+    Other,
+}
+
+impl ListError {
+    pub(crate) fn from_server_message(message: String) -> Self {
+        let kind = if contains_words(&message, &["deleted"]) && !contains_words(&message, &["not", "deleted"]) {
+            ListErrorKind::MessageDeleted
+        } else {
+            ListErrorKind::Other
+        };
+        ListError {
+            code: response_code(&message),
+            message,
+            kind,
+        }
+    }
+}
 
 impl From<ParseIntError> for ListError {
     fn from(value: ParseIntError) -> Self {
         ListError {
-            message: format!("could not parse list response numbers: {}", value.to_string()),
+            message: format!("could not parse list response numbers: {}", value),
+            code: None,
+            kind: ListErrorKind::Other,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RetrieveError {
+    pub message: String,
+    /// The RFC 2449 response code of the server, like `SYS/PERM` for `-ERR [SYS/PERM] account disabled`
+    pub code: Option<String>,
+    /// The bytes that were received before the retrieve was interrupted, if any
+    pub partial: Option<Vec<u8>>,
+    pub kind: RetrieveErrorKind,
+}
+
+/// The kind of RetrieveError, so you can, for example, treat an empty mailbox as a normal outcome
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetrieveErrorKind {
+    /// There was no message to retrieve, e.g. for `retrieve_last_as_string` on an empty mailbox
+    EmptyMailbox,
+    Other,
+}
+
+impl Display for RetrieveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for RetrieveError {}
+
+impl From<String> for RetrieveError {
+    fn from(message: String) -> Self {
+        RetrieveError {
+            code: response_code(&message),
+            message,
+            partial: None,
+            kind: RetrieveErrorKind::Other,
         }
     }
 }
 
-impl_err_with_from_str!(RetrieveError);
+impl From<ReadError> for RetrieveError {
+    fn from(value: ReadError) -> Self {
+        RetrieveError {
+            code: response_code(&value.message),
+            message: value.message,
+            partial: Some(value.partial).filter(|partial| !partial.is_empty()),
+            kind: RetrieveErrorKind::Other,
+        }
+    }
+}
 
 impl From<std::io::Error> for RetrieveError {
     fn from(value: std::io::Error) -> Self {
         RetrieveError {
-            message: format!("could not retrieve message: {}", value.to_string()),
+            message: format!("could not retrieve message: {}", value),
+            code: None,
+            partial: None,
+            kind: RetrieveErrorKind::Other,
         }
     }
 }
@@ -157,24 +380,258 @@ impl From<ListError> for RetrieveError {
     fn from(value: ListError) -> Self {
         RetrieveError {
             message: value.message,
+            code: value.code,
+            partial: None,
+            kind: RetrieveErrorKind::Other,
+        }
+    }
+}
+
+impl From<FromUtf8Error> for RetrieveError {
+    fn from(value: FromUtf8Error) -> Self {
+        RetrieveError {
+            message: format!("could not decode message as UTF-8: {}", value.utf8_error()),
+            code: None,
+            partial: Some(value.into_bytes()),
+            kind: RetrieveErrorKind::Other,
+        }
+    }
+}
+
+impl From<Utf8Error> for RetrieveError {
+    fn from(value: Utf8Error) -> Self {
+        RetrieveError {
+            message: format!("could not decode message as UTF-8: {}", value),
+            code: None,
+            partial: None,
+            kind: RetrieveErrorKind::Other,
+        }
+    }
+}
+
+impl From<ParseIntError> for RetrieveError {
+    fn from(value: ParseIntError) -> Self {
+        RetrieveError {
+            message: format!("could not parse retrieve response numbers: {}", value),
+            code: None,
+            partial: None,
+            kind: RetrieveErrorKind::Other,
         }
     }
 }
 
 impl_err_with_from_str!(ResetError);
 
+impl From<ParseIntError> for ResetError {
+    fn from(value: ParseIntError) -> Self {
+        ResetError {
+            message: format!("could not parse reset response numbers: {}", value),
+            code: None,
+        }
+    }
+}
+
 impl_err_with_from_str!(DeleteError);
 
+impl From<ListError> for DeleteError {
+    fn from(value: ListError) -> Self {
+        DeleteError {
+            message: value.message,
+            code: value.code,
+        }
+    }
+}
+
+impl From<ParseIntError> for DeleteError {
+    fn from(value: ParseIntError) -> Self {
+        DeleteError {
+            message: format!("could not parse delete response numbers: {}", value),
+            code: None,
+        }
+    }
+}
+
 impl_err_with_from_str!(NoopError);
 
+impl From<ParseIntError> for NoopError {
+    fn from(value: ParseIntError) -> Self {
+        NoopError {
+            message: format!("could not parse noop response numbers: {}", value),
+            code: None,
+        }
+    }
+}
+
 impl_err_with_from_str!(UIDLError);
 
 impl From<ParseIntError> for UIDLError {
     fn from(value: ParseIntError) -> Self {
         UIDLError {
-            message: format!("could not parse UIDL message id as a number: {}", value.to_string()),
+            message: format!("could not parse UIDL message id as a number: {}", value),
+            code: None,
+        }
+    }
+}
+
+impl_err_with_kind!(TopError, TopErrorKind);
+
+/// The kind of TopError, so you can, for example, fall back to retrieving the entire message when TOP is not supported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopErrorKind {
+    /// The server does not implement the (optional) TOP command
+    NotSupported,
+    Other,
+}
+
+// phrases servers use when they don't know a command
+const UNSUPPORTED_COMMAND_PHRASES: [&str; 5] = ["not supported", "not implemented", "unknown command", "unrecognized command", "invalid command"];
+
+impl TopError {
+    pub(crate) fn from_server_message(message: String) -> Self {
+        let lowercase = message.to_lowercase();
+        let kind = if UNSUPPORTED_COMMAND_PHRASES.iter().any(|phrase| lowercase.contains(phrase)) {
+            TopErrorKind::NotSupported
+        } else {
+            TopErrorKind::Other
+        };
+        TopError {
+            code: response_code(&message),
+            message,
+            kind,
         }
     }
 }
 
-impl_err_with_from_str!(TopError);
+impl From<ListError> for TopError {
+    fn from(value: ListError) -> Self {
+        TopError {
+            message: value.message,
+            code: value.code,
+            kind: TopErrorKind::Other,
+        }
+    }
+}
+
+impl From<ParseIntError> for TopError {
+    fn from(value: ParseIntError) -> Self {
+        TopError {
+            message: format!("could not parse top response numbers: {}", value),
+            code: None,
+            kind: TopErrorKind::Other,
+        }
+    }
+}
+
+impl_err_with_from_str!(CapaError);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::{read_greeting, read_multi_response, read_response, MultilineOptions};
+
+    #[test]
+    fn test_top_error_from_unsupported_command_response() {
+        let data = b"-ERR command not supported\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_multi_response(&mut slice, MultilineOptions::default()).map_err(TopError::from_server_message).unwrap_err();
+
+        assert_eq!(actual.kind, TopErrorKind::NotSupported);
+        assert_eq!(actual.message, "command not supported".to_string());
+    }
+
+    #[test]
+    fn test_top_error_from_other_response() {
+        let actual = TopError::from_server_message("no such message".to_string());
+
+        assert_eq!(actual.kind, TopErrorKind::Other);
+    }
+
+    #[test]
+    fn test_connection_error_from_in_use_response() {
+        let data = b"-ERR [IN-USE] mailbox locked by another session\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_response(&mut slice).map_err(|err| ConnectionError::from_server_message(err.into())).unwrap_err();
+
+        assert_eq!(actual.kind, ConnectionErrorKind::MailboxLocked);
+    }
+
+    #[test]
+    fn test_connection_error_from_lock_phrasing() {
+        let actual = ConnectionError::from_server_message("maildrop already locked".to_string());
+
+        assert_eq!(actual.kind, ConnectionErrorKind::MailboxLocked);
+        assert_eq!(ConnectionError::from_server_message("mailbox in use".to_string()).kind, ConnectionErrorKind::MailboxLocked);
+        assert_eq!(ConnectionError::from_server_message("invalid password".to_string()).kind, ConnectionErrorKind::Other);
+    }
+
+    #[test]
+    fn test_list_error_from_deleted_phrasing() {
+        let actual = ListError::from_server_message("message 3 already deleted".to_string());
+
+        assert_eq!(actual.kind, ListErrorKind::MessageDeleted);
+        assert_eq!(ListError::from_server_message("message 3 is undeleted".to_string()).kind, ListErrorKind::Other);
+        assert_eq!(ListError::from_server_message("message 3 not deleted".to_string()).kind, ListErrorKind::Other);
+    }
+
+    #[test]
+    fn test_connection_error_from_blocked_account_is_not_locked() {
+        let actual = ConnectionError::from_server_message("-ERR account blocked".to_string());
+
+        assert_eq!(actual.kind, ConnectionErrorKind::Other);
+        assert_eq!(ConnectionError::from_server_message("account blocked for abuse".to_string()).kind, ConnectionErrorKind::Other);
+        assert_eq!(ConnectionError::from_server_message("unlock your account first".to_string()).kind, ConnectionErrorKind::Other);
+    }
+
+    #[test]
+    fn test_pop3_error_source_is_specific_error() {
+        let err: Pop3Error = StatError::from("invalid stat response".to_string()).into();
+
+        let source = err.source().unwrap();
+
+        assert_eq!(source.to_string(), "invalid stat response".to_string());
+        assert!(source.downcast_ref::<StatError>().is_some());
+    }
+
+    #[test]
+    fn test_error_from_response_with_code() {
+        let data = b"-ERR [SYS/PERM] account disabled\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual: StatError = String::from(read_response(&mut slice).unwrap_err()).into();
+
+        assert_eq!(actual.code, Some("SYS/PERM".to_string()));
+        assert_eq!(actual.message, "[SYS/PERM] account disabled".to_string());
+    }
+
+    #[test]
+    fn test_connection_error_from_refused_greeting() {
+        let data = b"-ERR [SYS/TEMP] service not available\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_greeting(&mut slice).map_err(ConnectionError::from_greeting).unwrap_err();
+
+        assert_eq!(actual.kind, ConnectionErrorKind::ServerRefused);
+        assert_eq!(actual.code, Some("SYS/TEMP".to_string()));
+        assert_eq!(ConnectionError::from_greeting(ResponseError::Server("invalid greeting from server: * OK".to_string())).kind, ConnectionErrorKind::Other);
+    }
+
+    #[test]
+    fn test_top_error_from_parse_int_error() {
+        let actual: TopError = "-1".parse::<u32>().unwrap_err().into();
+
+        assert_eq!(actual.kind, TopErrorKind::Other);
+        assert_eq!(actual.message, "could not parse top response numbers: invalid digit found in string".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_error_from_utf8_error_keeps_bytes() {
+        let bytes = b"Subject: caf\xe9".to_vec();
+
+        let actual: RetrieveError = String::from_utf8(bytes.clone()).unwrap_err().into();
+
+        assert_eq!(actual.message, "could not decode message as UTF-8: incomplete utf-8 byte sequence from index 12".to_string());
+        assert_eq!(actual.partial, Some(bytes));
+    }
+}
\ No newline at end of file
@@ -1,31 +1,112 @@
-use std::io::Write;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::marker::PhantomData;
-use std::net::TcpStream;
-use std::sync::Arc;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use rustls::{ClientConnection, StreamOwned};
+use rustls::ClientConfig;
+use sha2::{Digest, Sha256};
+use socket2::{SockRef, TcpKeepalive};
 
 use reader::read_response;
 
-use crate::client_config::create_rustls_config;
-use crate::reader::read_multi_response;
-use crate::responses::{ItemResponse, ListResponse, RetrieveResponse, StatResponse, TopResponse, UIDLItem, UIDLResponse};
+use crate::auth::{apop_timestamp, select_auth_method, AuthChoice};
+use crate::client_config::{create_rustls_config, create_rustls_config_with_store};
+use crate::mbox::write_mbox_entry;
+use crate::mechanism::SessionIo;
+use crate::reader::{read_continuation_response, read_greeting, read_multi_response, read_multi_response_into, read_multi_response_with_partial, read_sasl_outcome, MultilineOptions, ReadError, ResponseError};
+use crate::transcript::{RecordingReader, RedactedString};
 
+mod auth;
+mod build_info;
 mod client_config;
+#[cfg(feature = "time")]
+mod date;
+mod parallel;
 mod reader;
 mod errors;
+mod mbox;
+mod mechanism;
+mod messages;
+#[cfg(feature = "native-tls")]
+mod native;
 mod responses;
+mod shared;
+mod stream;
+mod transaction;
+mod transcript;
 
+pub use auth::{ApopDigest, AuthMethod};
+pub use build_info::{build_info, BuildInfo};
+pub use client_config::RootCertificates;
+pub use rustls::RootCertStore;
 pub use errors::*;
+pub use mechanism::{Apop, AuthMechanism, AuthSession, CramMd5, SaslPlain, UserPass, XOAuth2};
+pub use messages::Messages;
+pub use responses::*;
+pub use shared::SharedPop3Client;
+pub use stream::{Pop3Stream, Security, TlsInfo};
+pub use transaction::DeletionTransaction;
+pub use transcript::TranscriptEntry;
 
-/// The Pop3Client allows you to connect to a POP3 server and perform actions on it
-pub struct Pop3Client {
-    stream: StreamOwned<ClientConnection, TcpStream>,
+/// The default port for POP3 over TLS
+pub const DEFAULT_PORT: u16 = 995;
+
+/// The default port for POP3 without TLS, which is also the port for STARTTLS
+pub const DEFAULT_PLAINTEXT_PORT: u16 = 110;
+
+/// The Pop3Client allows you to connect to a POP3 server and perform actions on it.
+/// The stream is normally a `Pop3Stream` set up by the builder, but any `Read + Write` works, e.g. an in-memory mock in tests
+pub struct Pop3Client<S: Read + Write = Pop3Stream> {
+    // only None after `into_inner` took it, at which point the client can no longer be used
+    // buffered, so bytes the server sent after a response are kept for the next read
+    stream: Option<BufReader<S>>,
+    // handle to the socket (if there is one) for setting socket options, whatever the stream wraps it in
+    socket: Option<TcpStream>,
+    auth_method: Option<AuthMethod>,
+    transcript: Option<Vec<TranscriptEntry>>,
+    capabilities: Option<CapaResponse>,
+    // what CAPA returned before logging in, which can differ from after (e.g. the SASL mechanisms)
+    pre_login_capabilities: Option<CapaResponse>,
+    // the result of the last CAPA, which stays valid for the rest of the session
+    capa_cache: Option<CapaResponse>,
+    greeting: String,
+    deadline: Option<Instant>,
+    read_only: bool,
+    multiline: MultilineOptions,
+    stat_cache: Option<StatCache>,
+    // messages marked as deleted in this session, which the server leaves out of LIST until RSET
+    deleted: BTreeSet<u32>,
+    // the sizes LIST reported in this session, so deleted messages can still be shown with theirs
+    listed_sizes: HashMap<u32, u64>,
+}
+
+struct StatCache {
+    ttl: Duration,
+    last: Option<(Instant, StatResponse)>,
 }
 
-impl Drop for Pop3Client {
+const STREAM_TAKEN: &str = "stream is only taken when the client is consumed";
+
+// how long dropping the client may block on sending QUIT, when the connection is stuck
+const QUIT_ON_DROP_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl<S: Read + Write> Drop for Pop3Client<S> {
     fn drop(&mut self) {
-        let _ = self.invoke("QUIT");
+        if self.stream.is_some() {
+            // QUIT is best effort, so if we can't set the timeout, we still try to send it
+            if let Some(socket) = &self.socket {
+                let _ = socket.set_write_timeout(Some(QUIT_ON_DROP_TIMEOUT));
+            }
+            // a deadline that passed should not keep us from ending the session
+            self.deadline = None;
+            let _ = self.invoke("QUIT");
+        }
     }
 }
 
@@ -35,38 +116,213 @@ impl Pop3Client {
         Pop3ClientBuilder {
             host: None,
             port: None,
+            security: Security::default(),
             username: None,
             password: None,
+            auth: AuthChoice::Method(AuthMethod::UserPass),
+            capture_transcript: false,
+            root_certificates: RootCertificates::default(),
+            root_store: None,
+            tcp_keepalive: None,
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            timeout: None,
+            read_only: false,
+            lenient_terminator: false,
+            max_lines: None,
+            apop_digest: ApopDigest::default(),
+            probe_capabilities: false,
+            verify_session: false,
+            capa_before_login: false,
+            stat_cache: None,
             type_state: Default::default(),
         }
     }
 
+    /// Create a builder that is ready to `connect_host`, using the `POP3_HOST`, `POP3_USER` and `POP3_PASS` environment variables.
+    /// `POP3_SECURITY` is optional: `tls` (the default), `starttls` or `plaintext`. The older `POP3_TLS` is used when it is
+    /// not set, with `true` for TLS and `false` for plaintext. `POP3_PORT` is optional, and defaults to 995 for TLS and 110 otherwise
+    pub fn builder_from_env() -> Result<Pop3ClientBuilder<Pop3ClientBuilderConnect>, ConnectionError> {
+        Pop3Client::builder_from_vars(|name| std::env::var(name).ok())
+    }
+
+    // the variables come from a lookup, so tests don't have to change the environment of the process
+    fn builder_from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Pop3ClientBuilder<Pop3ClientBuilderConnect>, ConnectionError> {
+        let required_var = |name: &str| var(name).ok_or_else(|| format!("missing required environment variable {name}"));
+        let host = required_var("POP3_HOST")?;
+        let user = required_var("POP3_USER")?;
+        let password = required_var("POP3_PASS")?;
+        let security = match (var("POP3_SECURITY"), var("POP3_TLS")) {
+            (Some(security), _) => match security.to_ascii_lowercase().as_str() {
+                "tls" => Security::ImplicitTls,
+                "starttls" => Security::StartTls,
+                "plaintext" => Security::Plaintext,
+                _ => return Err(format!("environment variable POP3_SECURITY is {security}, expected tls, starttls or plaintext").into()),
+            },
+            (None, Some(tls)) if tls.eq_ignore_ascii_case("true") => Security::ImplicitTls,
+            (None, Some(tls)) if tls.eq_ignore_ascii_case("false") => Security::Plaintext,
+            (None, Some(tls)) => return Err(format!("environment variable POP3_TLS is {tls}, expected true or false").into()),
+            (None, None) => Security::ImplicitTls,
+        };
+        let port = match var("POP3_PORT") {
+            Some(port) => port.parse().map_err(|_| format!("environment variable POP3_PORT is not a valid port: {port}"))?,
+            None => default_port(security),
+        };
+
+        Ok(Pop3Client::builder().host(&host).port(port).security(security).username(&user).password(&password))
+    }
+
+    /// The negotiated TLS version, cipher suite and server certificates, or None when the connection is not encrypted
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.stream.as_ref().expect(STREAM_TAKEN).get_ref().tls_info()
+    }
+
+    /// Into inner ends the POP3 session with QUIT and closes the TLS session, returning the underlying TCP stream.
+    /// No more commands can be issued after this, since the client is consumed
+    pub fn into_inner(mut self) -> Result<TcpStream, ConnectionError> {
+        self.invoke("QUIT")?;
+        self.read_response()?;
+        self.stream.take().expect(STREAM_TAKEN).into_inner().into_tcp_stream()
+    }
+
+    /// Upgrade the plaintext connection to TLS with STLS (RFC 2595)
+    fn start_tls(&mut self, host: &str, config: Arc<ClientConfig>) -> Result<(), ConnectionError> {
+        self.invoke("STLS")?;
+        self.read_response().map_err(|err| format!("server refused STLS: {err}"))?;
+        // anything after the +OK arrived before the handshake, so it was not encrypted and could have been injected
+        if !self.stream.as_ref().expect(STREAM_TAKEN).buffer().is_empty() {
+            return Err("server sent data after accepting STLS, before the TLS handshake".to_string().into());
+        }
+        let tcp_stream = self.stream.take().expect(STREAM_TAKEN).into_inner().into_tcp_stream()?;
+        self.stream = Some(BufReader::new(Pop3Stream::tls(host, config, tcp_stream)?));
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Pop3Client<S> {
+    pub(crate) fn new(stream: S, socket: Option<TcpStream>) -> Pop3Client<S> {
+        Pop3Client {
+            stream: Some(BufReader::new(stream)),
+            socket,
+            auth_method: None,
+            transcript: None,
+            capabilities: None,
+            pre_login_capabilities: None,
+            capa_cache: None,
+            greeting: String::new(),
+            deadline: None,
+            read_only: false,
+            multiline: MultilineOptions::default(),
+            stat_cache: None,
+            deleted: BTreeSet::new(),
+            listed_sizes: HashMap::new(),
+        }
+    }
+
+    /// The authentication method that was used to log in, or None if the client connected without logging in
+    pub fn auth_method(&self) -> Option<AuthMethod> {
+        self.auth_method
+    }
+
+    /// The capabilities the server advertised before logging in. Only set when CAPA ran before the login,
+    /// which `auth_auto` and `sasl_plain` do to pick a mechanism, or when the builder was told to `capa_before_login`
+    pub fn pre_login_capabilities(&self) -> Option<&CapaResponse> {
+        self.pre_login_capabilities.as_ref()
+    }
+
+    /// The APOP timestamp from the greeting of this connection, if the server sent one
+    pub fn apop_timestamp(&self) -> Option<&str> {
+        apop_timestamp(&self.greeting)
+    }
+
+    /// Set a deadline after which every command fails with a timeout error, or None to remove it.
+    /// This is checked before sending each command, so a command that was already sent can still finish after the deadline
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Run the given operation with a deadline of timeout from now, e.g. to give up on fetching all messages after 30 seconds.
+    /// An earlier deadline that was already set stays in effect. Afterwards, the previous deadline (if any) is restored
+    pub fn with_timeout<R>(&mut self, timeout: Duration, operation: impl FnOnce(&mut Pop3Client<S>) -> R) -> R {
+        let deadline = Instant::now() + timeout;
+        let previous = self.deadline.replace(self.deadline.map_or(deadline, |current| current.min(deadline)));
+        let result = operation(self);
+        self.deadline = previous;
+        result
+    }
+
+    /// The commands sent and responses received so far. Empty unless the builder was told to `capture_transcript`
+    pub fn transcript(&self) -> &[TranscriptEntry] {
+        self.transcript.as_deref().unwrap_or_default()
+    }
+
     /// Stat requests the number of messages and size in the inbox
     pub fn stat(&mut self) -> Result<StatResponse, StatError> {
+        if let Some(StatCache { ttl, last: Some((at, stat)) }) = &self.stat_cache {
+            if at.elapsed() < *ttl {
+                return Ok(stat.clone());
+            }
+        }
         self.invoke("STAT")?;
         let response = self.read_response()?;
-        Ok(response.try_into()?)
+        let stat: StatResponse = response.try_into()?;
+        if let Some(cache) = &mut self.stat_cache {
+            cache.last = Some((Instant::now(), stat.clone()));
+        }
+        Ok(stat)
     }
 
     /// List generates a list of all message ids, with sizes
     pub fn list(&mut self) -> Result<ListResponse, ListError> {
         self.invoke("LIST")?;
         let response = self.read_multi_response()?;
-        Ok(response.try_into()?)
+        let list: ListResponse = response.try_into()?;
+        self.listed_sizes.extend(list.messages.iter().map(|message| (message.message_id, message.size)));
+        Ok(list)
+    }
+
+    /// List all messages, including the ones marked as deleted in this session (which LIST leaves out), ordered by message id.
+    /// The flag is true for messages that will be deleted on QUIT. A deleted message that was never listed in this session has size 0
+    pub fn list_including_deleted(&mut self) -> Result<Vec<(ItemResponse, bool)>, ListError> {
+        let mut messages: Vec<(ItemResponse, bool)> = self.list()?.messages
+            .into_iter()
+            .map(|message| (message, false))
+            .collect();
+        messages.extend(self.deleted.iter().map(|message_id| {
+            let size = self.listed_sizes.get(message_id).copied().unwrap_or(0);
+            (ItemResponse { message_id: *message_id, size }, true)
+        }));
+        messages.sort_by_key(|(message, _)| message.message_id);
+        Ok(messages)
     }
 
     /// List with a given message_id will return the id and size for that message_Id
-    pub fn list_id(&mut self, message_id: i32) -> Result<ItemResponse, ListError> {
+    pub fn list_id(&mut self, message_id: u32) -> Result<ItemResponse, ListError> {
+        // the server would refuse it anyway, so we can tell why without a round trip
+        if self.deleted.contains(&message_id) {
+            return Err(ListError {
+                message: format!("message {message_id} is marked as deleted"),
+                code: None,
+                kind: ListErrorKind::MessageDeleted,
+            });
+        }
         self.invoke(&format!("LIST {message_id}"))?;
-        let response = self.read_response()?;
-        Ok(response.try_into()?)
+        let response = self.read_response().map_err(ListError::from_server_message)?;
+        response.try_into()
+    }
+
+    /// The message numbers of the messages in the mailbox (according to LIST), e.g. to check whether a stored number is still valid
+    pub fn message_numbers(&mut self) -> Result<Vec<u32>, ListError> {
+        Ok(self.list()?.messages
+            .iter()
+            .map(|m| m.message_id)
+            .collect())
     }
 
     /// List the last x messages
-    pub fn list_last(&mut self, number_of_messages: i32) -> Result<ListResponse, ListError> {
-        self.invoke(&format!("LIST"))?;
-        let response = self.read_multi_response()?;
-        let response: ListResponse = response.try_into()?;
+    pub fn list_last(&mut self, number_of_messages: u32) -> Result<ListResponse, ListError> {
+        let response = self.list()?;
         let last_ten = response.messages
             .into_iter()
             .rev()
@@ -78,83 +334,274 @@ impl Pop3Client {
         })
     }
 
+    /// List the messages that are larger than the given number of bytes
+    pub fn list_larger_than(&mut self, bytes: u64) -> Result<ListResponse, ListError> {
+        self.list_filtered(|message| message.size > bytes)
+    }
+
+    /// List the messages that are smaller than the given number of bytes
+    pub fn list_smaller_than(&mut self, bytes: u64) -> Result<ListResponse, ListError> {
+        self.list_filtered(|message| message.size < bytes)
+    }
+
+    fn list_filtered(&mut self, keep: impl Fn(&ItemResponse) -> bool) -> Result<ListResponse, ListError> {
+        let response = self.list()?;
+        Ok(ListResponse {
+            messages: response.messages.into_iter().filter(keep).collect(),
+        })
+    }
+
     /// Retrieve as string retrieves the content of the message as a string
-    pub fn retrieve_as_string(&mut self, message_id: i32) -> Result<RetrieveResponse, RetrieveError> {
+    pub fn retrieve_as_string(&mut self, message_id: u32) -> Result<RetrieveResponse, RetrieveError> {
         self.invoke(&format!("RETR {message_id}"))?;
-        let response = self.read_multi_response()?;
+        let response = self.read_multi_response_with_partial()?;
         Ok(RetrieveResponse {
             message_id,
             data: response,
         })
     }
 
+    /// Retrieve the message into the buffer, which is cleared first. Reusing one buffer for many messages saves an allocation
+    /// per message. The buffer gets the same data as `retrieve_as_string` returns, and is empty when retrieving fails
+    pub fn retrieve_into(&mut self, message_id: u32, buffer: &mut Vec<u8>) -> Result<(), RetrieveError> {
+        self.invoke(&format!("RETR {message_id}"))?;
+        let options = self.multiline;
+        Ok(self.read_with(|mut reader| read_multi_response_into(&mut reader, buffer, options))?)
+    }
+
+    /// Retrieve the message together with the SHA-256 hash of its data, e.g. to find duplicates across mailboxes,
+    /// or to check that downloading it again gives the same message. The hash is over the bytes of the message as it was
+    /// sent: without the dot-stuffing of the server, with the line endings as received, up to and including the line ending
+    /// before the terminating period
+    pub fn retrieve_with_hash(&mut self, message_id: u32) -> Result<(RetrieveResponse, [u8; 32]), RetrieveError> {
+        let message = self.retrieve_as_string(message_id)?;
+        let hash = Sha256::digest(message.data.as_bytes()).into();
+        Ok((message, hash))
+    }
+
+    /// Retrieve only the body of the message, which is everything after the first blank line.
+    /// A message without a body gives an empty string
+    pub fn retrieve_body(&mut self, message_id: u32) -> Result<String, RetrieveError> {
+        let message = self.retrieve_as_string(message_id)?;
+        Ok(message.split_headers_body().1.to_string())
+    }
+
     /// Retrieve the content of the last message as a string
     pub fn retrieve_last_as_string(&mut self) -> Result<RetrieveResponse, RetrieveError> {
         let last = self.list()?;
         let last_message = last.messages.last().ok_or(RetrieveError {
             message: "no messages available".to_string(),
+            code: None,
+            partial: None,
+            kind: RetrieveErrorKind::EmptyMailbox,
         })?;
-        self.invoke(&format!("RETR {}", last_message.message_id))?;
-        let response = self.read_multi_response()?;
+        let message_id = last_message.message_id;
+        self.invoke(&format!("RETR {message_id}"))?;
+        let response = self.read_multi_response_with_partial()?;
         Ok(RetrieveResponse {
-            message_id: -1,
+            message_id,
             data: response,
         })
     }
 
-    /// Retrieve the content of the message and pass it into a writer
-    pub fn retrieve(&mut self, message_id: i32, writer: &mut impl Write) -> Result<(), RetrieveError> {
+    /// Messages lists the message ids, and returns an iterator that retrieves each message when it advances.
+    /// Use this to process (and delete) messages one at a time, without keeping all of them in memory
+    pub fn messages(&mut self) -> Result<Messages<'_, S>, ListError> {
+        let message_ids = self.message_numbers()?;
+        Ok(Messages::new(self, message_ids))
+    }
+
+    /// Retrieve the content of the message and pass it into a writer, returning the number of bytes written
+    pub fn retrieve(&mut self, message_id: u32, writer: &mut impl Write) -> Result<usize, RetrieveError> {
         let as_string = self.retrieve_as_string(message_id)?;
-        writer.write(as_string.data.as_bytes())?;
-        Ok(())
+        write_message(&as_string, writer)
     }
 
-    /// Retrieve the content of the last message and pass it into a writer
-    pub fn retrieve_last(&mut self, writer: &mut impl Write) -> Result<(), RetrieveError> {
+    /// Retrieve the message into the writer only if its size (according to LIST) is at most max_bytes.
+    /// Returns whether the message was retrieved, so false means it was skipped because it was too large
+    pub fn retrieve_if_under(&mut self, message_id: u32, max_bytes: u64, writer: &mut impl Write) -> Result<bool, RetrieveError> {
+        if self.list_id(message_id)?.size > max_bytes {
+            return Ok(false);
+        }
+        self.retrieve(message_id, writer)?;
+        Ok(true)
+    }
+
+    /// Retrieve the content of the last message and pass it into a writer, returning the number of bytes written
+    pub fn retrieve_last(&mut self, writer: &mut impl Write) -> Result<usize, RetrieveError> {
         let as_string = self.retrieve_last_as_string()?;
-        writer.write(as_string.data.as_bytes())?;
-        Ok(())
+        write_message(&as_string, writer)
+    }
+
+    /// Save all messages to the given directory, as `<message_id>.eml`, returning the number of messages saved
+    pub fn save_all_to_dir(&mut self, dir: impl AsRef<Path>) -> Result<u32, RetrieveError> {
+        self.save_all_to_dir_with_line_ending(dir, LineEnding::default())
+    }
+
+    /// Save all messages to the given directory, as `<message_id>.eml`, with the given line endings.
+    /// The files contain the messages as they were sent, so without the dot-stuffing of the server
+    pub fn save_all_to_dir_with_line_ending(&mut self, dir: impl AsRef<Path>, line_ending: LineEnding) -> Result<u32, RetrieveError> {
+        let message_ids = self.message_numbers()?;
+        for message_id in &message_ids {
+            let message = self.retrieve_as_string(*message_id)?;
+            let path = dir.as_ref().join(format!("{message_id}.eml"));
+            std::fs::write(&path, line_ending.apply(&message.data))
+                .map_err(|err| format!("could not save message {message_id} to {}: {err}", path.display()))?;
+        }
+        Ok(message_ids.len() as u32)
+    }
+
+    /// Export all messages to the writer as a single mbox (mboxrd) file, returning the number of messages exported.
+    /// An empty mailbox writes nothing
+    pub fn export_mbox(&mut self, writer: &mut impl Write) -> Result<u32, Pop3Error> {
+        let message_ids = self.message_numbers()?;
+        for message_id in &message_ids {
+            let message = self.retrieve_as_string(*message_id)?;
+            write_mbox_entry(&message, writer).map_err(RetrieveError::from)?;
+        }
+        Ok(message_ids.len() as u32)
+    }
+
+    /// Retrieve every message and pass it to the handler, deleting the message only when the handler returns Ok, e.g. once
+    /// it is safely stored. Stops at the first error, leaving that message on the server. Returns the number of messages drained.
+    /// The deletions only take effect at QUIT, so call `reset` if you want to keep the messages after all
+    pub fn drain<E: From<Pop3Error>>(&mut self, mut handle: impl FnMut(&RetrieveResponse) -> Result<(), E>) -> Result<u32, E> {
+        self.ensure_writable().map_err(|err| Pop3Error::from(DeleteError::from(err)))?;
+        let message_ids = self.message_numbers().map_err(Pop3Error::from)?;
+        for message_id in &message_ids {
+            let message = self.retrieve_as_string(*message_id).map_err(Pop3Error::from)?;
+            handle(&message)?;
+            self.delete(*message_id).map_err(Pop3Error::from)?;
+        }
+        Ok(message_ids.len() as u32)
     }
 
     /// Reset unmarks all messages that were set as deleted
     pub fn reset(&mut self) -> Result<(), ResetError> {
+        self.reset_verbose().map(|_| ())
+    }
+
+    /// Like `reset`, but returns the text the server sent after `+OK`, like `maildrop has 2 messages`
+    pub fn reset_verbose(&mut self) -> Result<String, ResetError> {
+        self.ensure_writable()?;
+        self.clear_stat_cache();
         self.invoke("RSET")?;
-        self.read_response()?;
-        Ok(())
+        let response = self.read_response()?;
+        self.deleted.clear();
+        Ok(response)
+    }
+
+    /// Start a transaction for deleting messages, which sends RSET to undo the deletions unless you commit it.
+    /// RSET undoes every deletion of the session, so this fails when messages are already marked as deleted
+    /// (including by an earlier, committed transaction). See `DeletionTransaction`
+    pub fn transaction(&mut self) -> Result<DeletionTransaction<'_, S>, DeleteError> {
+        if !self.deleted.is_empty() {
+            return Err(format!("{} messages are already marked as deleted, which a RSET of the transaction would undo", self.deleted.len()).into());
+        }
+        Ok(DeletionTransaction::new(self))
     }
 
     /// Delete marks a given message, by its message_id, as deleted
-    pub fn delete(&mut self, message_id: i32) -> Result<(), DeleteError> {
+    pub fn delete(&mut self, message_id: u32) -> Result<(), DeleteError> {
+        self.delete_verbose(message_id).map(|_| ())
+    }
+
+    /// Like `delete`, but returns the text the server sent after `+OK`, like `message 3 deleted`
+    pub fn delete_verbose(&mut self, message_id: u32) -> Result<String, DeleteError> {
+        self.ensure_writable()?;
+        self.clear_stat_cache();
         self.invoke(&format!("DELE {message_id}"))?;
-        self.read_response()?;
-        Ok(())
+        let response = self.read_response()?;
+        self.deleted.insert(message_id);
+        Ok(response)
+    }
+
+    /// Delete many marks all given messages as deleted, returning how many were marked.
+    /// If some deletes fail, the others are still attempted and the error lists the ones that failed
+    pub fn delete_many(&mut self, message_ids: &[u32]) -> Result<u32, DeleteError> {
+        self.ensure_writable()?;
+        let mut deleted = 0;
+        let mut failures = Vec::new();
+        for message_id in message_ids {
+            match self.delete(*message_id) {
+                Ok(_) => deleted += 1,
+                Err(err) => failures.push(format!("{message_id} ({})", err.message)),
+            }
+        }
+        if failures.is_empty() {
+            Ok(deleted)
+        } else {
+            Err(DeleteError {
+                message: format!("could not delete messages {}", failures.join(", ")),
+                code: None,
+            })
+        }
+    }
+
+    /// Delete range marks the messages from start up to and including end as deleted, returning how many were marked
+    pub fn delete_range(&mut self, start: u32, end: u32) -> Result<u32, DeleteError> {
+        let message_ids: Vec<u32> = (start..=end).collect();
+        self.delete_many(&message_ids)
+    }
+
+    /// Delete all marks every message in the mailbox as deleted, returning how many were marked
+    pub fn delete_all(&mut self) -> Result<u32, DeleteError> {
+        self.ensure_writable()?;
+        let message_ids = self.message_numbers()?;
+        self.delete_many(&message_ids)
     }
 
     /// Noop is a no-op, which returns nothing. Can be used to test the connection
     pub fn noop(&mut self) -> Result<(), NoopError> {
+        self.noop_verbose().map(|_| ())
+    }
+
+    /// Like `noop`, but returns the text the server sent after `+OK`
+    pub fn noop_verbose(&mut self) -> Result<String, NoopError> {
+        self.clear_stat_cache();
         self.invoke("NOOP")?;
-        self.read_response()?;
-        Ok(())
+        Ok(self.read_response()?)
     }
 
     /// UIDL generates a list of all message ids plus their unique ids
     pub fn uidl(&mut self) -> Result<UIDLResponse, UIDLError> {
+        self.ensure_supported("UIDL")?;
         self.invoke("UIDL")?;
         let response = self.read_multi_response()?;
-        Ok(response.try_into()?)
+        response.try_into()
     }
 
     /// UIDL with a given message_id will return the message_id and its unique id
-    pub fn uidl_with_id(&mut self, message_id: i32) -> Result<UIDLItem, UIDLError> {
+    pub fn uidl_with_id(&mut self, message_id: u32) -> Result<UIDLItem, UIDLError> {
+        self.ensure_supported("UIDL")?;
         self.invoke(&format!("UIDL {message_id}"))?;
         let response = self.read_response()?;
-        Ok(response.try_into()?)
+        response.try_into()
+    }
+
+    /// New since returns the message ids of the messages whose unique id is not in the given set of seen unique ids
+    pub fn new_since(&mut self, seen: &HashSet<String>) -> Result<Vec<u32>, UIDLError> {
+        let response = self.uidl()?;
+        Ok(response.messages
+            .into_iter()
+            .filter(|m| !seen.contains(&m.unique_id))
+            .map(|m| m.message_id)
+            .collect())
+    }
+
+    /// Overview lists the size and unique id of every message, combining LIST and UIDL.
+    /// When the two responses don't agree on which messages exist, the missing size or unique id is None
+    pub fn overview(&mut self) -> Result<Vec<MessageOverview>, Pop3Error> {
+        let list = self.list()?;
+        let uidl = self.uidl()?;
+        Ok(MessageOverview::join(list, uidl))
     }
 
     /// Top retrieves the number_of_lines of the message (chosen by its message_id)
-    pub fn top(&mut self, message_id: i32, number_of_lines: i32) -> Result<TopResponse, TopError> {
+    pub fn top(&mut self, message_id: u32, number_of_lines: u32) -> Result<TopResponse, TopError> {
+        self.ensure_top_supported()?;
         self.invoke(&format!("TOP {message_id} {number_of_lines}"))?;
-        let response = self.read_multi_response()?;
+        let response = self.read_multi_response().map_err(TopError::from_server_message)?;
         Ok(TopResponse {
             message_id,
             number_of_lines,
@@ -162,23 +609,282 @@ impl Pop3Client {
         })
     }
 
+    /// Top all lists the messages and runs TOP for every one of them, e.g. to show a preview of the inbox.
+    /// When the server does not support TOP, this fails on the first message with `TopErrorKind::NotSupported`
+    pub fn top_all(&mut self, number_of_lines: u32) -> Result<Vec<TopResponse>, TopError> {
+        // this error already says TOP is not supported, the prefix below is for the -ERR of the server
+        self.ensure_top_supported()?;
+        let message_ids = self.message_numbers()?;
+        message_ids.iter()
+            .map(|message_id| self.top(*message_id, number_of_lines).map_err(|err| match err.kind {
+                TopErrorKind::NotSupported => TopError {
+                    message: format!("server does not support TOP: {}", err.message),
+                    ..err
+                },
+                TopErrorKind::Other => err,
+            }))
+            .collect()
+    }
+
+    /// Capa lists the capabilities of the server. The result is cached, see `cached_capabilities`
+    pub fn capa(&mut self) -> Result<CapaResponse, CapaError> {
+        self.invoke("CAPA")?;
+        let response = self.read_multi_response()?;
+        let capabilities: CapaResponse = response.try_into()?;
+        self.capa_cache = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    /// The capabilities of the server, running CAPA only when it did not run yet in this session
+    pub fn capabilities(&mut self) -> Result<&CapaResponse, CapaError> {
+        let capabilities = match self.capa_cache.take() {
+            Some(capabilities) => capabilities,
+            None => self.capa()?,
+        };
+        Ok(self.capa_cache.insert(capabilities))
+    }
+
+    /// What the last CAPA of this session returned, without contacting the server. None when CAPA did not run (successfully) yet
+    pub fn cached_capabilities(&self) -> Option<&CapaResponse> {
+        self.capa_cache.as_ref()
+    }
+
+    fn ensure_writable(&self) -> Result<(), String> {
+        if self.read_only {
+            Err("client is read-only".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn clear_stat_cache(&mut self) {
+        if let Some(cache) = &mut self.stat_cache {
+            cache.last = None;
+        }
+    }
+
+    /// If capabilities were probed at connect, refuse to send commands the server did not advertise
+    fn ensure_supported(&self, capability: &str) -> Result<(), String> {
+        match &self.capabilities {
+            Some(capabilities) if !capabilities.supports(capability) => Err(format!("{capability} is not supported by the server")),
+            _ => Ok(()),
+        }
+    }
+
+    fn ensure_top_supported(&self) -> Result<(), TopError> {
+        self.ensure_supported("TOP").map_err(|message| TopError {
+            message,
+            code: None,
+            kind: TopErrorKind::NotSupported,
+        })
+    }
+
     fn invoke(&mut self, command: &str) -> Result<usize, String> {
-        Ok(self.stream.write(format!("{command}\r\n").as_bytes()).map_err(|err| err.to_string())?)
+        self.check_deadline()?;
+        check_single_line(command)?;
+        self.record(|| TranscriptEntry::sent(command));
+        self.write_command(command)
+    }
+
+    /// Invoke for commands that consist entirely of secrets, like SASL responses, which should not be in the transcript
+    fn invoke_secret(&mut self, command: &str) -> Result<usize, String> {
+        self.check_deadline()?;
+        check_single_line(command)?;
+        self.record(TranscriptEntry::sent_secret);
+        self.write_command(command)
+    }
+
+    fn check_deadline(&self) -> Result<(), String> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err("timed out: the deadline of the client has passed".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    // flushes after every command, so it can't stay behind in a buffering stream while we wait for the response
+    fn write_command(&mut self, command: &str) -> Result<usize, String> {
+        let line = format!("{command}\r\n");
+        let stream = self.stream.as_mut().expect(STREAM_TAKEN).get_mut();
+        // write_all instead of write, because write can accept fewer bytes than we give it
+        stream.write_all(line.as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(|err| err.to_string())?;
+        Ok(line.len())
+    }
+
+    fn record(&mut self, entry: impl FnOnce() -> TranscriptEntry) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.push(entry());
+        }
+    }
+
+    /// Passes the stream to the given read function, recording what was read if we are capturing a transcript
+    fn read_with<T, E>(&mut self, read: impl FnOnce(&mut dyn BufRead) -> Result<T, E>) -> Result<T, E> {
+        let stream = self.stream.as_mut().expect(STREAM_TAKEN);
+        match &mut self.transcript {
+            Some(transcript) => {
+                let mut recorder = RecordingReader::new(stream);
+                let result = read(&mut recorder);
+                transcript.push(recorder.into_entry());
+                result
+            }
+            None => read(stream),
+        }
     }
 
     fn read_response(&mut self) -> Result<String, String> {
-        read_response(&mut self.stream)
+        self.read_with(|mut reader| read_response(&mut reader)).map_err(String::from)
     }
 
     fn read_multi_response(&mut self) -> Result<String, String> {
-        read_multi_response(&mut self.stream)
+        let options = self.multiline;
+        self.read_with(|mut reader| read_multi_response(&mut reader, options))
+    }
+
+    fn read_multi_response_with_partial(&mut self) -> Result<String, ReadError> {
+        let options = self.multiline;
+        self.read_with(|mut reader| read_multi_response_with_partial(&mut reader, options))
+    }
+
+    fn read_greeting(&mut self) -> Result<String, ResponseError> {
+        self.read_with(|mut reader| read_greeting(&mut reader))
+    }
+
+    fn read_continuation_response(&mut self) -> Result<String, ResponseError> {
+        self.read_with(|mut reader| read_continuation_response(&mut reader))
+    }
+
+    fn read_sasl_outcome(&mut self) -> Result<Option<String>, ResponseError> {
+        self.read_with(|mut reader| read_sasl_outcome(&mut reader))
+    }
+}
+
+fn default_port(security: Security) -> u16 {
+    match security {
+        Security::ImplicitTls => DEFAULT_PORT,
+        Security::StartTls | Security::Plaintext => DEFAULT_PLAINTEXT_PORT,
+    }
+}
+
+impl<S: Read + Write> SessionIo for Pop3Client<S> {
+    fn send_command(&mut self, command: &str, secret: bool) -> Result<(), String> {
+        if secret {
+            self.invoke_secret(command)?;
+        } else {
+            self.invoke(command)?;
+        }
+        Ok(())
+    }
+
+    fn receive_response(&mut self) -> Result<String, ResponseError> {
+        self.read_with(|mut reader| read_response(&mut reader))
+    }
+
+    fn receive_continuation(&mut self) -> Result<String, ResponseError> {
+        self.read_continuation_response()
+    }
+
+    fn receive_sasl_outcome(&mut self) -> Result<Option<String>, ResponseError> {
+        self.read_sasl_outcome()
+    }
+}
+
+fn builtin_mechanism(method: AuthMethod, user: &str, pass: &str, digest: ApopDigest) -> Arc<dyn AuthMechanism> {
+    match method {
+        AuthMethod::Apop => Arc::new(Apop::new(user, pass).digest(digest)),
+        AuthMethod::CramMd5 => Arc::new(CramMd5::new(user, pass)),
+        AuthMethod::SaslPlain => Arc::new(SaslPlain::new(user, pass)),
+        AuthMethod::XOAuth2 => Arc::new(XOAuth2::new(user, pass)),
+        // a custom mechanism is passed to the builder as is, so this is only reached for USER/PASS
+        AuthMethod::UserPass | AuthMethod::Custom => Arc::new(UserPass::new(user, pass)),
+    }
+}
+
+// an embedded CR or LF would let one command smuggle in others, e.g. through a username
+fn check_single_line(command: &str) -> Result<(), String> {
+    if command.contains(['\r', '\n']) {
+        return Err("command contains a line break, which is not allowed".to_string());
+    }
+    Ok(())
+}
+
+fn write_message(message: &RetrieveResponse, writer: &mut impl Write) -> Result<usize, RetrieveError> {
+    writer.write_all(message.data.as_bytes())?;
+    Ok(message.data.len())
+}
+
+fn connect_tcp(host: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream, ConnectionError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let addresses = resolve(host, port, timeout)?;
+
+    let mut last_error = None;
+    for address in addresses {
+        let result = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()).filter(|remaining| !remaining.is_zero()) {
+                Some(remaining) => TcpStream::connect_timeout(&address, remaining),
+                None => break,
+            },
+            None => TcpStream::connect(address),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(match last_error {
+        Some(err) => err.into(),
+        None => format!("could not set up client connection: timed out connecting to {host}").into(),
+    })
+}
+
+/// Resolve the host on a separate thread when there is a timeout, because the standard library has no way to bound DNS resolution
+fn resolve(host: &str, port: u16, timeout: Option<Duration>) -> Result<Vec<SocketAddr>, ConnectionError> {
+    resolve_with(host, port, timeout, |host, port| (host, port).to_socket_addrs().map(Iterator::collect))
+}
+
+// the lookup is a parameter so tests do not depend on the resolver of the machine
+fn resolve_with(
+    host: &str,
+    port: u16,
+    timeout: Option<Duration>,
+    lookup: fn(&str, u16) -> std::io::Result<Vec<SocketAddr>>,
+) -> Result<Vec<SocketAddr>, ConnectionError> {
+    let resolution_error = |message: String| ConnectionError {
+        message,
+        code: None,
+        kind: ConnectionErrorKind::DnsResolution,
+    };
+    let result = match timeout {
+        Some(timeout) => {
+            let (sender, receiver) = mpsc::channel();
+            let owned_host = host.to_string();
+            thread::spawn(move || sender.send(lookup(&owned_host, port)));
+            receiver.recv_timeout(timeout)
+                .map_err(|_| resolution_error(format!("timed out resolving host {host}")))?
+        }
+        None => lookup(host, port),
+    };
+    match result {
+        Ok(addresses) if !addresses.is_empty() => Ok(addresses),
+        Ok(_) => Err(resolution_error(format!("could not resolve host {host}: no addresses found"))),
+        Err(err) => Err(resolution_error(format!("could not resolve host {host}: {err}"))),
     }
 }
 
+fn set_tcp_keepalive(tcp_stream: &TcpStream, keepalive: Duration) -> std::io::Result<()> {
+    let settings = TcpKeepalive::new().with_time(keepalive);
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "windows"))]
+    let settings = settings.with_interval(keepalive);
+    SockRef::from(tcp_stream).set_tcp_keepalive(&settings)
+}
+
 pub trait Pop3ClientBuilderState {}
 
+#[derive(Clone)]
 pub struct Pop3ClientBuilderCredsUsername {}
+#[derive(Clone)]
 pub struct Pop3ClientBuilderCredsPassword {}
+#[derive(Clone)]
 pub struct Pop3ClientBuilderConnect {}
 
 impl Pop3ClientBuilderState for Pop3ClientBuilderCredsUsername {}
@@ -186,104 +892,1598 @@ impl Pop3ClientBuilderState for Pop3ClientBuilderCredsPassword {}
 impl Pop3ClientBuilderState for Pop3ClientBuilderConnect {}
 
 /// The builder for the POP3 client
+#[derive(Clone)]
 pub struct Pop3ClientBuilder<T: Pop3ClientBuilderState> {
     host: Option<String>,
     port: Option<u16>,
+    security: Security,
     username: Option<String>,
-    password: Option<String>,
+    password: Option<RedactedString>,
+    auth: AuthChoice,
+    capture_transcript: bool,
+    root_certificates: RootCertificates,
+    root_store: Option<RootCertStore>,
+    tcp_keepalive: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    read_only: bool,
+    lenient_terminator: bool,
+    max_lines: Option<usize>,
+    apop_digest: ApopDigest,
+    probe_capabilities: bool,
+    verify_session: bool,
+    capa_before_login: bool,
+    stat_cache: Option<Duration>,
     type_state: PhantomData<T>,
 }
 
-impl Pop3ClientBuilder<Pop3ClientBuilderCredsUsername> {
-    /// Set the username for the POP3 client connection
-    pub fn username(self, user: &str) -> Pop3ClientBuilder<Pop3ClientBuilderCredsPassword> {
+impl<T: Pop3ClientBuilderState> Pop3ClientBuilder<T> {
+    /// Record every command sent to and response received from the server, with passwords redacted.
+    /// Retrieve the recording with `Pop3Client::transcript`
+    pub fn capture_transcript(self, capture: bool) -> Self {
         Pop3ClientBuilder {
-            host: self.host,
-            port: self.port,
-            username: Some(user.to_string()),
-            password: self.password,
-            type_state: Default::default(),
+            capture_transcript: capture,
+            ..self
         }
     }
 
-    /// If you do not have a username and password, use this method to acknowledge that, allowing you to
-    /// connect to the server without credentials
-    pub fn no_login(self) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+    /// Set the host to connect to with `connect_host`
+    pub fn host(self, host: &str) -> Self {
         Pop3ClientBuilder {
-            host: self.host,
-            port: self.port,
-            username: None,
-            password: None,
-            type_state: Default::default(),
+            host: Some(host.to_string()),
+            ..self
         }
     }
-}
 
-impl Pop3ClientBuilder<Pop3ClientBuilderCredsPassword> {
-    /// Set the password for the POP3 client connection
-    pub fn password(self, password: &str) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+    /// Set the port to connect to, overriding the port of the Pop3Connection. Defaults to 995 (POP3 over TLS) for `connect_host`,
+    /// or 110 when its `security` is not implicit TLS
+    pub fn port(self, port: u16) -> Self {
         Pop3ClientBuilder {
-            host: self.host,
-            port: self.port,
-            username: self.username,
-            password: Some(password.to_string()),
-            type_state: Default::default(),
+            port: Some(port),
+            ..self
         }
     }
-}
-
-impl Pop3ClientBuilder<Pop3ClientBuilderConnect> {
-    /// Connect to the POP3 server using the details specified in Pop3Connection
-    pub fn connect(self, Pop3Connection { host, port }: Pop3Connection) -> Result<Pop3Client, ConnectionError> {
-        let config = create_rustls_config()?;
-        let server_name = host.to_string().try_into()?;
-        let connection = ClientConnection::new(Arc::new(config), server_name)?;
-        let tcp_stream = TcpStream::connect(format!("{}:{}", host, port))?;
-        let stream = StreamOwned::new(connection, tcp_stream);
-
-        let mut client = Pop3Client {
-            stream,
-        };
 
-        client.read_response()?;
+    /// Set how `connect_host` secures the connection. Defaults to implicit TLS
+    pub fn security(self, security: Security) -> Self {
+        Pop3ClientBuilder {
+            security,
+            ..self
+        }
+    }
 
-        // if the client was created with a username and password, we need to login
-        if let (Some(user), Some(pass)) = (self.username, self.password) {
-            client.invoke(&format!("USER {user}"))?;
-            client.read_response()?;
-            client.invoke(&format!("PASS {pass}"))?;
-            client.read_response()?;
+    /// Choose where the root certificates for verifying the server come from. Defaults to the trust store of the operating system
+    pub fn root_certificates(self, root_certificates: RootCertificates) -> Self {
+        Pop3ClientBuilder {
+            root_certificates,
+            ..self
         }
+    }
 
-        Ok(client)
+    /// Verify the server with exactly the root certificates in this store, e.g. trust anchors you load from your own directory.
+    /// Takes precedence over `root_certificates`, and there is no fallback to the bundled or system certificates
+    pub fn root_store(self, root_store: RootCertStore) -> Self {
+        Pop3ClientBuilder {
+            root_store: Some(root_store),
+            ..self
+        }
     }
-}
 
-/// The connection details of the POP3 server
-pub struct Pop3Connection<'a> {
-    host: &'a str,
-    port: u16,
-}
+    /// Enable TCP keepalive, sending probes after the connection has been idle for the given duration (and, where the platform
+    /// supports it, at that interval afterwards). This prevents NAT devices from silently dropping long-idle connections.
+    /// Unlike `noop`, this works on the TCP level and does not involve the POP3 server
+    pub fn tcp_keepalive(self, keepalive: Option<Duration>) -> Self {
+        Pop3ClientBuilder {
+            tcp_keepalive: keepalive,
+            ..self
+        }
+    }
 
-impl Pop3Connection<'_> {
-    /// Create a new Pop3Connection with the given host and port
-    pub fn new(host: &str, port: u16) -> Pop3Connection {
-        Pop3Connection { host, port }
+    /// Limit how long resolving the host and setting up the TCP connection may take, together.
+    /// Without a timeout, a dead DNS server or unreachable host can block the connect for minutes
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        Pop3ClientBuilder {
+            connect_timeout: Some(timeout),
+            ..self
+        }
     }
 
-    /// Create a new Pop3Connection with the host and port of (Microsoft) Outlook
-    pub fn outlook() -> Pop3Connection<'static> {
-        Pop3Connection {
-            host: "outlook.office365.com",
-            port: 995,
+    /// Limit how long reading a response from the server may block, e.g. when the server stops responding mid-session
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        Pop3ClientBuilder {
+            read_timeout: Some(timeout),
+            ..self
         }
     }
 
-    /// Create a new Pop3Connection with the host and port of (Google) Gmail
-    pub fn gmail() -> Pop3Connection<'static> {
-        Pop3Connection {
-            host: "pop.gmail.com",
-            port: 995,
+    /// Limit how long sending a command to the server may block
+    pub fn write_timeout(self, timeout: Duration) -> Self {
+        Pop3ClientBuilder {
+            write_timeout: Some(timeout),
+            ..self
         }
     }
+
+    /// A default for the connect, read and write timeouts, for when nothing should block longer than the given time.
+    /// A specific timeout (`connect_timeout`, `read_timeout` or `write_timeout`) that is set takes precedence over this one
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Pop3ClientBuilder {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Remove every timeout set with `connect_timeout`, `read_timeout`, `write_timeout` or `timeout`, so nothing is limited
+    pub fn no_timeouts(self) -> Self {
+        Pop3ClientBuilder {
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            timeout: None,
+            ..self
+        }
+    }
+
+    /// Refuse commands that change the mailbox (`delete` and friends, and `reset`), without sending anything to the server.
+    /// Useful for scripts that should only ever read
+    pub fn read_only(self, read_only: bool) -> Self {
+        Pop3ClientBuilder {
+            read_only,
+            ..self
+        }
+    }
+
+    /// Accept a multiline response whose terminating period is glued to the last line of content (`last line.\r\n`
+    /// instead of `last line\r\n.\r\n`), as some nonconforming servers send. Because such a period can't be told apart
+    /// from content, it only counts as the terminator when the connection closes right after it. A read that times out
+    /// could be a slow server, so it only completes the response when the data so far ends with a terminator on its own line
+    /// that is missing (part of) its line ending. This is best effort and off by default, as it can turn a dropped connection
+    /// into a message that is silently cut off after a line ending with a period
+    pub fn lenient_terminator(self, lenient_terminator: bool) -> Self {
+        Pop3ClientBuilder {
+            lenient_terminator,
+            ..self
+        }
+    }
+
+    /// Fail a multiline response (like RETR or LIST) once its content has more than the given number of lines, which
+    /// protects against pathological messages with millions of tiny lines. The rest of the response is not read,
+    /// so the session can't be used for other commands after that error
+    pub fn max_lines(self, max_lines: usize) -> Self {
+        Pop3ClientBuilder {
+            max_lines: Some(max_lines),
+            ..self
+        }
+    }
+
+    /// The digest to use when logging in with APOP. Defaults to MD5, as specified by the RFC.
+    /// Only change this when you know the server supports the stronger digest
+    pub fn apop_digest(self, digest: ApopDigest) -> Self {
+        Pop3ClientBuilder {
+            apop_digest: digest,
+            ..self
+        }
+    }
+
+    /// Run CAPA after connecting and remember the result, so that commands the server does not advertise (TOP, UIDL)
+    /// fail immediately instead of after a round trip. Servers that do not support CAPA are treated as supporting everything
+    pub fn probe_capabilities(self, probe: bool) -> Self {
+        Pop3ClientBuilder {
+            probe_capabilities: probe,
+            ..self
+        }
+    }
+
+    /// Run CAPA after connecting (and logging in), and fail the connect when the server rejects it. This confirms that the
+    /// session is usable before you send commands, e.g. for servers that allow access without logging in, see `no_login`.
+    /// Only use this with servers that support CAPA
+    pub fn verify_session(self, verify: bool) -> Self {
+        Pop3ClientBuilder {
+            verify_session: verify,
+            ..self
+        }
+    }
+
+    /// Run CAPA after the greeting, before logging in, and keep the result in `Pop3Client::pre_login_capabilities`.
+    /// `auth_auto` and `sasl_plain` always do this, because they need the SASL mechanisms to log in
+    pub fn capa_before_login(self, capa: bool) -> Self {
+        Pop3ClientBuilder {
+            capa_before_login: capa,
+            ..self
+        }
+    }
+
+    /// Cache the result of `stat` for the given time, so repeated calls (e.g. for a dashboard) don't all go to the server.
+    /// NOOP, DELE and RSET clear the cache. Without this option, every `stat` sends STAT
+    pub fn stat_cache(self, ttl: Duration) -> Self {
+        Pop3ClientBuilder {
+            stat_cache: Some(ttl),
+            ..self
+        }
+    }
+
+    fn transition<U: Pop3ClientBuilderState>(self) -> Pop3ClientBuilder<U> {
+        Pop3ClientBuilder {
+            host: self.host,
+            port: self.port,
+            security: self.security,
+            username: self.username,
+            password: self.password,
+            auth: self.auth,
+            capture_transcript: self.capture_transcript,
+            root_certificates: self.root_certificates,
+            root_store: self.root_store,
+            tcp_keepalive: self.tcp_keepalive,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            timeout: self.timeout,
+            read_only: self.read_only,
+            lenient_terminator: self.lenient_terminator,
+            max_lines: self.max_lines,
+            apop_digest: self.apop_digest,
+            probe_capabilities: self.probe_capabilities,
+            verify_session: self.verify_session,
+            capa_before_login: self.capa_before_login,
+            stat_cache: self.stat_cache,
+            type_state: Default::default(),
+        }
+    }
+}
+
+impl Pop3ClientBuilder<Pop3ClientBuilderCredsUsername> {
+    /// Set the username for the POP3 client connection
+    pub fn username(self, user: &str) -> Pop3ClientBuilder<Pop3ClientBuilderCredsPassword> {
+        Pop3ClientBuilder {
+            username: Some(user.to_string()),
+            ..self.transition()
+        }
+    }
+
+    /// If you do not have a username and password, use this method to acknowledge that, allowing you to
+    /// connect to the server without credentials. An `-ERR` greeting still fails the connect,
+    /// and `verify_session` lets you check that the server accepts commands without logging in
+    pub fn no_login(self) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+        Pop3ClientBuilder {
+            username: None,
+            password: None,
+            ..self.transition()
+        }
+    }
+
+    /// Log in with the username and password, using the strongest authentication method the server supports.
+    /// Afterwards, `Pop3Client::auth_method` tells you which method was used. Over a plaintext connection, this fails
+    /// instead of sending the password as is (with PLAIN or USER/PASS), choose the method yourself to allow that.
+    /// XOAUTH2 is never chosen, as it needs a token instead of a password
+    pub fn auth_auto(self, user: &str, password: &str) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+        Pop3ClientBuilder {
+            username: Some(user.to_string()),
+            password: Some(RedactedString::new(password)),
+            auth: AuthChoice::Auto,
+            ..self.transition()
+        }
+    }
+
+    /// Log in using SASL CRAM-MD5, for servers that advertise it. The secret is never sent to the server,
+    /// only an HMAC-MD5 of the server challenge
+    pub fn cram_md5(self, user: &str, secret: &str) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+        Pop3ClientBuilder {
+            username: Some(user.to_string()),
+            password: Some(RedactedString::new(secret)),
+            auth: AuthChoice::Method(AuthMethod::CramMd5),
+            ..self.transition()
+        }
+    }
+
+    /// Log in using APOP, which sends a digest of the timestamp in the server greeting and the secret, instead of the secret itself.
+    /// See `apop_digest` for using a stronger digest than MD5
+    pub fn apop(self, user: &str, secret: &str) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+        Pop3ClientBuilder {
+            username: Some(user.to_string()),
+            password: Some(RedactedString::new(secret)),
+            auth: AuthChoice::Method(AuthMethod::Apop),
+            ..self.transition()
+        }
+    }
+
+    /// Log in using SASL XOAUTH2 with an OAuth 2.0 access token, which Gmail and Outlook require.
+    /// Getting (and refreshing) the token is up to you
+    pub fn xoauth2(self, user: &str, token: &str) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+        Pop3ClientBuilder {
+            username: Some(user.to_string()),
+            password: Some(RedactedString::new(token)),
+            auth: AuthChoice::Method(AuthMethod::XOAuth2),
+            ..self.transition()
+        }
+    }
+
+    /// Log in with an `AuthMechanism`, e.g. your own implementation of a provider-specific flow.
+    /// Afterwards, `Pop3Client::auth_method` returns what the `method` of the mechanism returns
+    pub fn auth(self, mechanism: impl AuthMechanism + 'static) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+        Pop3ClientBuilder {
+            username: None,
+            password: None,
+            auth: AuthChoice::Custom(Arc::new(mechanism)),
+            ..self.transition()
+        }
+    }
+
+    /// Log in using SASL PLAIN, which some servers prefer over USER/PASS
+    pub fn sasl_plain(self, user: &str, password: &str) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+        Pop3ClientBuilder {
+            username: Some(user.to_string()),
+            password: Some(RedactedString::new(password)),
+            auth: AuthChoice::Method(AuthMethod::SaslPlain),
+            ..self.transition()
+        }
+    }
+}
+
+impl Pop3ClientBuilder<Pop3ClientBuilderCredsPassword> {
+    /// Set the password for the POP3 client connection
+    pub fn password(self, password: &str) -> Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+        Pop3ClientBuilder {
+            password: Some(RedactedString::new(password)),
+            ..self.transition()
+        }
+    }
+}
+
+impl Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+    /// Connect to the POP3 server using the details specified in Pop3Connection
+    pub fn connect(self, connection: Pop3Connection) -> Result<Pop3Client, Pop3Error> {
+        self.check_required_auth(&connection)?;
+        let Pop3Connection { host, port, security, .. } = connection;
+        let port = self.port.unwrap_or(port);
+        self.connect_to(host, port, security)
+    }
+
+    /// Connect like `connect`, but try up to `attempts` times when the connection is refused, reset, closed or times out
+    /// (`ConnectionErrorKind::Transport`) or the mailbox is locked, waiting `backoff` before the first retry and twice as long before every following one.
+    /// Other errors, like an `AuthError` for bad credentials or a rejected certificate, are returned right away, because retrying would not help.
+    /// Zero attempts is a `ConnectionError`, without connecting
+    pub fn connect_retrying(self, connection: Pop3Connection, attempts: u32, backoff: Duration) -> Result<Pop3Client, Pop3Error> {
+        if attempts == 0 {
+            return Err(ConnectionError::from("connect_retrying needs at least one attempt".to_string()).into());
+        }
+        let mut wait = backoff;
+        for _ in 1..attempts {
+            match self.clone().connect(connection) {
+                Err(Pop3Error::ConnectionError(ConnectionError { kind: ConnectionErrorKind::Transport | ConnectionErrorKind::MailboxLocked, .. })) => {
+                    thread::sleep(wait);
+                    wait = wait.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+        self.connect(connection)
+    }
+
+    // fail before connecting when a preset needs an auth method the builder was not set up for
+    pub(crate) fn check_required_auth(&self, connection: &Pop3Connection) -> Result<(), AuthError> {
+        match connection.required_auth {
+            Some(required) if self.auth.method() != Some(required) =>
+                Err(format!("{} requires {required} authentication", connection.host).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Connect to the POP3 server using the host, port and security set on the builder.
+    /// The port defaults to 995, or 110 when the security is not implicit TLS
+    pub fn connect_host(self) -> Result<Pop3Client, Pop3Error> {
+        let host = self.host.clone()
+            .ok_or_else(|| ConnectionError::from("no host set on the builder".to_string()))?;
+        let port = self.port.unwrap_or(default_port(self.security));
+        let security = self.security;
+        self.connect_to(&host, port, security)
+    }
+
+    /// Start the session over a stream you set up yourself, like a TLS stream from another library, or a mock in tests.
+    /// The builder logs in as usual, but options for setting up the connection (like `connect_timeout`) have no effect
+    pub fn connect_stream<S: Read + Write>(self, stream: S) -> Result<Pop3Client<S>, Pop3Error> {
+        self.start_session(stream, None, None, |_| Ok(()))
+    }
+
+    /// Connect over a Unix domain socket, like the one of a local Dovecot or a sidecar proxy. The connection is not encrypted
+    #[cfg(unix)]
+    pub fn connect_unix(self, path: impl AsRef<Path>) -> Result<Pop3Client<UnixStream>, Pop3Error> {
+        let stream = UnixStream::connect(path).map_err(ConnectionError::from)?;
+        // the socket does not leave the machine, so `auth_auto` may still send the password as is
+        self.start_session(stream, None, None, |_| Ok(()))
+    }
+
+    /// Connect to an address you resolved yourself, with implicit TLS, verifying the server with the given name.
+    /// Use this to pin a specific server behind round-robin DNS. The port of the address is used, not the one on the builder
+    pub fn connect_addr(self, addr: SocketAddr, server_name: &str) -> Result<Pop3Client, Pop3Error> {
+        let tcp_stream = match self.effective_connect_timeout() {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        }
+        .map_err(ConnectionError::from)?;
+        self.start_tcp_session(tcp_stream, server_name, Security::ImplicitTls)
+    }
+
+    pub(crate) fn effective_connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout.or(self.timeout)
+    }
+
+    pub(crate) fn configure_socket(&self, tcp_stream: &TcpStream) -> Result<(), ConnectionError> {
+        if let Some(keepalive) = self.tcp_keepalive {
+            set_tcp_keepalive(tcp_stream, keepalive)?;
+        }
+        tcp_stream.set_read_timeout(self.read_timeout.or(self.timeout))?;
+        tcp_stream.set_write_timeout(self.write_timeout.or(self.timeout))?;
+        Ok(())
+    }
+
+    fn connect_to(self, host: &str, port: u16, security: Security) -> Result<Pop3Client, Pop3Error> {
+        let tcp_stream = connect_tcp(host, port, self.effective_connect_timeout())?;
+        self.start_tcp_session(tcp_stream, host, security)
+    }
+
+    fn start_tcp_session(self, tcp_stream: TcpStream, host: &str, security: Security) -> Result<Pop3Client, Pop3Error> {
+        self.configure_socket(&tcp_stream)?;
+        let socket = tcp_stream.try_clone().ok();
+        let stream = match security {
+            Security::ImplicitTls => Pop3Stream::tls(host, self.rustls_config()?, tcp_stream)?,
+            Security::StartTls | Security::Plaintext => Pop3Stream::Plain(tcp_stream),
+        };
+
+        let start_tls_config = match security {
+            Security::StartTls => Some(self.rustls_config()?),
+            Security::ImplicitTls | Security::Plaintext => None,
+        };
+        self.start_session(stream, socket, Some(security), |client| match start_tls_config {
+            Some(config) => client.start_tls(host, config),
+            None => Ok(()),
+        })
+    }
+
+    // a root store passed to the builder replaces the root certificates
+    fn rustls_config(&self) -> Result<Arc<ClientConfig>, ConnectionError> {
+        let config = match &self.root_store {
+            Some(root_store) => create_rustls_config_with_store(root_store.clone())?,
+            None => create_rustls_config(self.root_certificates)?,
+        };
+        Ok(Arc::new(config))
+    }
+
+    /// Read the greeting, secure the connection if needed, and log in
+    fn start_session<S: Read + Write>(
+        self,
+        stream: S,
+        socket: Option<TcpStream>,
+        security: Option<Security>,
+        secure: impl FnOnce(&mut Pop3Client<S>) -> Result<(), ConnectionError>,
+    ) -> Result<Pop3Client<S>, Pop3Error> {
+        let mut client = Pop3Client::new(stream, socket);
+        client.transcript = self.capture_transcript.then(Vec::new);
+        client.read_only = self.read_only;
+        client.multiline = MultilineOptions { lenient_terminator: self.lenient_terminator, max_lines: self.max_lines };
+        client.stat_cache = self.stat_cache.map(|ttl| StatCache { ttl, last: None });
+
+        // every connection has its own greeting (and timestamp), so this is always read fresh
+        client.greeting = client.read_greeting().map_err(ConnectionError::from_greeting)?;
+        let greeting = client.greeting.clone();
+        secure(&mut client)?;
+
+        // RFC 2449 allows CAPA before authentication, which is the only way to learn the SASL mechanisms
+        let logs_in = self.username.is_some() && self.password.is_some();
+        let needs_capabilities = matches!(self.auth, AuthChoice::Auto | AuthChoice::Method(AuthMethod::SaslPlain));
+        if self.capa_before_login || (logs_in && needs_capabilities) {
+            // not every server supports CAPA, in which case we decide based on the greeting alone
+            client.pre_login_capabilities = client.capa().ok();
+        }
+
+        // log in with the mechanism of the builder, or with the username and password if the client was created with those
+        let mechanism = match (self.auth, self.username, self.password) {
+            (AuthChoice::Custom(mechanism), _, _) => Some(mechanism),
+            (choice, Some(user), Some(pass)) => {
+                let method = match choice.method() {
+                    Some(method) => method,
+                    None => select_auth_method(&greeting, client.pre_login_capabilities.as_ref(), security)?,
+                };
+                Some(builtin_mechanism(method, &user, pass.expose(), self.apop_digest))
+            }
+            _ => None,
+        };
+        if let Some(mechanism) = mechanism {
+            // the session borrows the client, so it gets the capabilities separately
+            let capabilities = client.pre_login_capabilities.take();
+            let login = mechanism.authenticate(&mut AuthSession::new(&mut client, &greeting, capabilities.as_ref()));
+            if login.is_ok() {
+                client.auth_method = Some(mechanism.method());
+            }
+            client.pre_login_capabilities = capabilities;
+            let secret = RedactedString::new(mechanism.secret().unwrap_or_default());
+            // what the server advertises can change once we are logged in
+            client.capa_cache = None;
+            // servers sometimes echo what they received, so make sure the password does not end up in errors or the transcript
+            client.transcript = client.transcript.take().map(|entries| entries.into_iter().map(|entry| entry.redacted(&secret)).collect());
+            login.map_err(|err| match err {
+                Pop3Error::AuthError(err) => Pop3Error::AuthError(AuthError {
+                    message: secret.redact_in(&err.message),
+                    ..err
+                }),
+                Pop3Error::ConnectionError(err) => Pop3Error::ConnectionError(ConnectionError {
+                    message: secret.redact_in(&err.message),
+                    ..err
+                }),
+                err => err,
+            })?;
+        }
+
+        // probe after login, because servers can advertise different capabilities before authentication
+        if self.verify_session {
+            let capabilities = client.capa().map_err(|err| ConnectionError {
+                message: format!("server did not accept the session: {}", err.message),
+                code: err.code,
+                kind: ConnectionErrorKind::Other,
+            })?;
+            client.capabilities = Some(capabilities);
+        } else if self.probe_capabilities {
+            client.capabilities = client.capa().ok();
+        }
+
+        Ok(client)
+    }
+}
+
+/// The connection details of the POP3 server
+#[derive(Debug, Clone, Copy)]
+pub struct Pop3Connection<'a> {
+    host: &'a str,
+    port: u16,
+    security: Security,
+    required_auth: Option<AuthMethod>,
+}
+
+impl Pop3Connection<'_> {
+    /// Create a new Pop3Connection with the given host and port, using implicit TLS
+    pub fn new(host: &str, port: u16) -> Pop3Connection<'_> {
+        Pop3Connection::custom(host, port, Security::ImplicitTls)
+    }
+
+    /// Create a new Pop3Connection with the given host, port and way of securing the connection
+    pub fn custom(host: &str, port: u16, security: Security) -> Pop3Connection<'_> {
+        Pop3Connection { host, port, security, required_auth: None }
+    }
+
+    /// Check that the server is reachable and speaks POP3 without logging in (which can lock the mailbox or trip rate limits),
+    /// e.g. for health checks. Connects (securing the connection as usual), reads the greeting and ends with QUIT.
+    /// The timeout applies to connecting and to every read and write. Returns the greeting, without `+OK`
+    pub fn probe(&self, timeout: Duration) -> Result<String, ConnectionError> {
+        let client = Pop3Client::builder()
+            .no_login()
+            .timeout(timeout)
+            .connect_to(self.host, self.port, self.security)
+            .map_err(|err| match err {
+                Pop3Error::ConnectionError(err) => err,
+                err => ConnectionError::from(err.to_string()),
+            })?;
+        Ok(client.greeting.clone())
+    }
+
+    /// Create a new Pop3Connection with the host and port of (Microsoft) Outlook
+    pub fn outlook() -> Pop3Connection<'static> {
+        Pop3Connection {
+            host: "outlook.office365.com",
+            port: DEFAULT_PORT,
+            security: Security::ImplicitTls,
+            required_auth: None,
+        }
+    }
+
+    /// Like `outlook`, but connecting fails immediately unless the builder logs in with `xoauth2`,
+    /// because Outlook no longer accepts passwords
+    pub fn outlook_oauth() -> Pop3Connection<'static> {
+        Pop3Connection {
+            required_auth: Some(AuthMethod::XOAuth2),
+            ..Pop3Connection::outlook()
+        }
+    }
+
+    /// Create a new Pop3Connection with the host and port of (Google) Gmail
+    pub fn gmail() -> Pop3Connection<'static> {
+        Pop3Connection {
+            host: "pop.gmail.com",
+            port: DEFAULT_PORT,
+            security: Security::ImplicitTls,
+            required_auth: None,
+        }
+    }
+
+    /// Like `gmail`, but connecting fails immediately unless the builder logs in with `xoauth2`,
+    /// because Gmail only accepts passwords for accounts with app passwords
+    pub fn gmail_oauth() -> Pop3Connection<'static> {
+        Pop3Connection {
+            required_auth: Some(AuthMethod::XOAuth2),
+            ..Pop3Connection::gmail()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writer that accepts at most three bytes per call
+    struct TrickleWriter {
+        written: Vec<u8>,
+    }
+
+    impl Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let accepted = buf.len().min(3);
+            self.written.extend_from_slice(&buf[..accepted]);
+            Ok(accepted)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_message_writes_everything_to_writer_accepting_few_bytes() {
+        let message = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\r\n\r\nA body that is longer than three bytes".to_string(),
+        };
+        let mut writer = TrickleWriter { written: vec![] };
+
+        let actual = write_message(&message, &mut writer).unwrap();
+
+        assert_eq!(actual, message.data.len());
+        assert_eq!(writer.written, message.data.as_bytes());
+    }
+
+    // the only test that touches these environment variables, so tests running in parallel don't interfere
+    #[test]
+    fn test_builder_from_vars() {
+        let from = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+            Pop3Client::builder_from_vars(|name| vars.get(name).cloned())
+        };
+        let required = [("POP3_HOST", "pop.example.com"), ("POP3_USER", "tim"), ("POP3_PASS", "secret")];
+
+        let missing = from(&required[1..]).err().unwrap();
+        assert_eq!(missing.message, "missing required environment variable POP3_HOST".to_string());
+
+        let actual = from(&[required.as_slice(), &[("POP3_PORT", "1995")]].concat()).unwrap();
+        assert_eq!(actual.host, Some("pop.example.com".to_string()));
+        assert_eq!(actual.port, Some(1995));
+        assert_eq!(actual.username, Some("tim".to_string()));
+        assert_eq!(actual.security, Security::ImplicitTls);
+
+        let plaintext = from(&[required.as_slice(), &[("POP3_TLS", "false")]].concat()).unwrap();
+        assert_eq!((plaintext.security, plaintext.port), (Security::Plaintext, Some(DEFAULT_PLAINTEXT_PORT)));
+
+        let start_tls = from(&[required.as_slice(), &[("POP3_TLS", "false"), ("POP3_SECURITY", "starttls")]].concat()).unwrap();
+        assert_eq!(start_tls.security, Security::StartTls);
+
+        assert!(from(&[required.as_slice(), &[("POP3_SECURITY", "ssl")]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_start_tls_rejects_data_sent_before_the_handshake() {
+        let (port, server) = fake_server("+OK ready\r\n", vec!["+OK begin TLS\r\n+OK injected\r\n"]);
+
+        let result = Pop3Client::builder()
+            .no_login()
+            .connect(Pop3Connection::custom("127.0.0.1", port, Security::StartTls));
+        let Err(Pop3Error::ConnectionError(actual)) = result else {
+            panic!("expected a connection error");
+        };
+
+        assert_eq!(actual.message, "server sent data after accepting STLS, before the TLS handshake".to_string());
+        assert_eq!(server.join().unwrap(), vec!["STLS"]);
+    }
+
+    #[test]
+    fn test_resolve_failure_is_dns_resolution_error() {
+        let failed = resolve_with("host.invalid", DEFAULT_PORT, Some(Duration::from_secs(5)), |_, _| Err(std::io::Error::other("no such host"))).err().unwrap();
+        let empty = resolve_with("host.invalid", DEFAULT_PORT, None, |_, _| Ok(vec![])).err().unwrap();
+        let slow = resolve_with("host.invalid", DEFAULT_PORT, Some(Duration::from_millis(10)), |_, _| {
+            thread::sleep(Duration::from_secs(1));
+            Ok(vec![])
+        }).err().unwrap();
+
+        assert_eq!(failed.kind, ConnectionErrorKind::DnsResolution);
+        assert_eq!(failed.message, "could not resolve host host.invalid: no such host".to_string());
+        assert_eq!(empty.kind, ConnectionErrorKind::DnsResolution);
+        assert_eq!(slow.kind, ConnectionErrorKind::DnsResolution);
+        assert_eq!(slow.message, "timed out resolving host host.invalid".to_string());
+        assert!(resolve("127.0.0.1", DEFAULT_PORT, Some(Duration::from_secs(5))).is_ok());
+    }
+
+    // plaintext server on localhost that sends the greeting, and then answers every command with the next response
+    fn fake_server(greeting: &'static str, responses: Vec<&'static str>) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(greeting.as_bytes()).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut commands = Vec::new();
+            for response in responses {
+                let mut command = String::new();
+                if reader.read_line(&mut command).unwrap() == 0 {
+                    break;
+                }
+                commands.push(command.trim_end().to_string());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            commands
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_connect_plaintext() {
+        let (port, server) = fake_server("+OK ready\r\n", vec!["+OK\r\n", "+OK\r\n", "+OK 2 320\r\n", "+OK bye\r\n"]);
+
+        let mut client = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext))
+            .unwrap();
+        let stat = client.stat().unwrap();
+        drop(client);
+
+        assert_eq!(stat.number_of_message, 2);
+        assert_eq!(server.join().unwrap(), vec!["USER tim", "PASS tanstaaf", "STAT", "QUIT"]);
+    }
+
+    #[test]
+    fn test_connect_no_login_with_rejected_session() {
+        let (port, server) = fake_server("+OK ready\r\n", vec!["-ERR [AUTH] log in first\r\n", "+OK bye\r\n"]);
+
+        let result = Pop3Client::builder()
+            .no_login()
+            .verify_session(true)
+            .connect(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext));
+        let Err(Pop3Error::ConnectionError(actual)) = result else {
+            panic!("expected a connection error");
+        };
+
+        assert_eq!(actual.message, "server did not accept the session: [AUTH] log in first".to_string());
+        assert_eq!(actual.code, Some("AUTH".to_string()));
+        assert_eq!(server.join().unwrap(), vec!["CAPA", "QUIT"]);
+    }
+
+    #[test]
+    fn test_retrieve_if_under_skips_large_message() {
+        let (port, server) = fake_server("+OK ready\r\n", vec!["+OK 1 5000\r\n", "+OK 2 120\r\n", "+OK\r\nSubject: small\r\n.\r\n", "+OK bye\r\n"]);
+        let mut client = Pop3Client::builder()
+            .no_login()
+            .connect(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext))
+            .unwrap();
+        let mut written = Vec::new();
+
+        let large = client.retrieve_if_under(1, 1000, &mut written).unwrap();
+        let small = client.retrieve_if_under(2, 1000, &mut written).unwrap();
+        drop(client);
+
+        assert!(!large);
+        assert!(small);
+        assert_eq!(server.join().unwrap(), vec!["LIST 1", "LIST 2", "RETR 2", "QUIT"]);
+    }
+
+    // in-memory stream that answers with a fixed script, and keeps what the client wrote
+    struct MockStream {
+        input: std::io::Cursor<Vec<u8>>,
+        written: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn mock_stream(server: &str) -> (MockStream, std::rc::Rc<std::cell::RefCell<Vec<u8>>>) {
+        let written = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let stream = MockStream {
+            input: std::io::Cursor::new(server.as_bytes().to_vec()),
+            written: written.clone(),
+        };
+        (stream, written)
+    }
+
+    fn mock_client(server: &str) -> (Pop3Client<MockStream>, std::rc::Rc<std::cell::RefCell<Vec<u8>>>) {
+        let (stream, written) = mock_stream(server);
+        (Pop3Client::new(stream, None), written)
+    }
+
+    #[test]
+    fn test_commands_over_mock_stream_are_framed() {
+        let (mut client, written) = mock_client("+OK\r\n1 120\r\n2 200\r\n.\r\n+OK 2 320\r\n");
+
+        let list = client.list().unwrap();
+        let stat = client.stat().unwrap();
+        drop(client);
+
+        assert_eq!(list.messages.len(), 2);
+        assert_eq!(stat.total_size, 320);
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "LIST\r\nSTAT\r\nQUIT\r\n".to_string());
+    }
+
+    #[test]
+    fn test_connect_stream_logs_in() {
+        let (stream, written) = mock_stream("+OK ready\r\n+OK\r\n+OK logged in\r\n");
+
+        let client = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect_stream(stream)
+            .unwrap();
+
+        assert_eq!(client.auth_method(), Some(AuthMethod::UserPass));
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "USER tim\r\nPASS tanstaaf\r\n".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_body_skips_headers() {
+        let (mut client, written) = mock_client("+OK\r\nSubject: test\r\n\r\nHello\r\n\r\nBye\r\n.\r\n");
+
+        let actual = client.retrieve_body(1).unwrap();
+
+        assert!(actual.starts_with("Hello\r\n\r\nBye"));
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "RETR 1\r\n".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_as_string_strips_terminator() {
+        let (mut client, _) = mock_client("+OK\r\nSubject: test\r\n\r\nHello\r\n.\r\n");
+
+        let actual = client.retrieve_as_string(1).unwrap();
+
+        assert_eq!(actual.data, "Subject: test\r\n\r\nHello\r\n".to_string());
+        assert!(!actual.data.ends_with('.'));
+        assert_eq!(actual.data_with_terminator(), "Subject: test\r\n\r\nHello\r\n.".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_as_string_keeps_last_line_with_literal_dot() {
+        let (mut client, _) = mock_client("+OK\r\nSubject: test\r\n\r\nHello\r\n..\r\n.\r\n");
+
+        let actual = client.retrieve_as_string(1).unwrap();
+
+        assert_eq!(actual.data, "Subject: test\r\n\r\nHello\r\n.\r\n".to_string());
+        assert_eq!(actual.lines().last(), Some("."));
+    }
+
+    #[test]
+    fn test_connect_addr_fails_when_nothing_listens() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let actual = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect_addr(addr, "localhost");
+
+        assert!(actual.is_err());
+    }
+
+    #[test]
+    fn test_connect_stream_rejected_password_is_auth_error() {
+        let (stream, _) = mock_stream("+OK ready\r\n+OK\r\n-ERR [AUTH] invalid password tanstaaf\r\n");
+
+        let result = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect_stream(stream);
+        let Err(Pop3Error::AuthError(actual)) = result else {
+            panic!("expected an auth error");
+        };
+
+        assert_eq!(actual.message, "[AUTH] invalid password ***".to_string());
+        assert_eq!(actual.code, Some("AUTH".to_string()));
+    }
+
+    #[test]
+    fn test_connect_stream_locked_mailbox_is_connection_error() {
+        let (stream, _) = mock_stream("+OK ready\r\n+OK\r\n-ERR [IN-USE] mailbox locked\r\n");
+
+        let result = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect_stream(stream);
+        let Err(Pop3Error::ConnectionError(actual)) = result else {
+            panic!("expected a connection error");
+        };
+
+        assert_eq!(actual.kind, ConnectionErrorKind::MailboxLocked);
+    }
+
+    #[test]
+    fn test_connect_stream_response_without_terminator_fails_by_default() {
+        let (stream, _) = mock_stream("+OK ready\r\n+OK\r\nthe end of a sentence.\r\n");
+
+        let mut client = Pop3Client::builder().no_login().connect_stream(stream).unwrap();
+        let Err(actual) = client.retrieve_as_string(1) else {
+            panic!("expected a retrieve error");
+        };
+
+        assert_eq!(actual.message, "connection closed before the end of the response".to_string());
+        assert_eq!(actual.partial, Some(b"+OK\r\nthe end of a sentence.\r\n".to_vec()));
+    }
+
+    #[test]
+    fn test_connect_stream_closed_after_user_is_connection_error() {
+        let (stream, _) = mock_stream("+OK ready\r\n");
+
+        let result = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect_stream(stream);
+
+        assert!(matches!(result, Err(Pop3Error::ConnectionError(_))));
+    }
+
+    // only passes on what was written when it is flushed, like a BufWriter
+    struct BufferingStream {
+        input: std::io::Cursor<Vec<u8>>,
+        buffer: Vec<u8>,
+        flushed: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+    }
+
+    impl Read for BufferingStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for BufferingStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buffer.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushed.borrow_mut().append(&mut self.buffer);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_commands_are_flushed() {
+        let flushed = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let stream = BufferingStream {
+            input: std::io::Cursor::new(b"+OK\r\n".to_vec()),
+            buffer: Vec::new(),
+            flushed: flushed.clone(),
+        };
+        let mut client = Pop3Client::new(stream, None);
+
+        client.noop().unwrap();
+
+        assert_eq!(String::from_utf8(flushed.borrow().clone()).unwrap(), "NOOP\r\n".to_string());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_connect_unix() {
+        let path = std::env::temp_dir().join(format!("pop3-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"+OK ready\r\n+OK\r\n+OK\r\n+OK 2 320\r\n").unwrap();
+            let mut received = String::new();
+            let _ = stream.read_to_string(&mut received);
+            received
+        });
+
+        let mut client = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect_unix(&path)
+            .unwrap();
+        let stat = client.stat().unwrap();
+        drop(client);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(stat.number_of_message, 2);
+        assert_eq!(server.join().unwrap(), "USER tim\r\nPASS tanstaaf\r\nSTAT\r\nQUIT\r\n".to_string());
+    }
+
+    #[test]
+    fn test_stat_cache_until_noop() {
+        let (stream, written) = mock_stream("+OK ready\r\n+OK 2 320\r\n+OK\r\n+OK 3 400\r\n");
+
+        let mut client = Pop3Client::builder()
+            .no_login()
+            .stat_cache(Duration::from_secs(60))
+            .connect_stream(stream)
+            .unwrap();
+        let first = client.stat().unwrap();
+        let cached = client.stat().unwrap();
+        client.noop().unwrap();
+        let refreshed = client.stat().unwrap();
+
+        assert_eq!(first.number_of_message, 2);
+        assert_eq!(cached.number_of_message, 2);
+        assert_eq!(refreshed.number_of_message, 3);
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "STAT\r\nNOOP\r\nSTAT\r\n".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_as_string_header_after_byte_order_mark() {
+        let (mut client, _) = mock_client("+OK\r\n\u{FEFF}Subject: test\r\n\r\nHello\r\n.\r\n");
+
+        let actual = client.retrieve_as_string(1).unwrap();
+
+        assert_eq!(actual.header("subject"), Some("test".to_string()));
+    }
+
+    #[test]
+    fn test_capa_before_login() {
+        let (stream, written) = mock_stream("+OK ready\r\n+OK\r\nTOP\r\nSASL PLAIN\r\n.\r\n+OK\r\n+OK logged in\r\n");
+
+        let client = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .capa_before_login(true)
+            .connect_stream(stream)
+            .unwrap();
+
+        assert!(client.pre_login_capabilities().unwrap().supports("SASL"));
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "CAPA\r\nUSER tim\r\nPASS tanstaaf\r\n".to_string());
+    }
+
+    #[test]
+    fn test_connect_stream_xoauth2_failure() {
+        // the details are base64 of {"status":"401"}
+        let (stream, written) = mock_stream("+OK ready\r\n+ eyJzdGF0dXMiOiI0MDEifQ==\r\n-ERR authentication failed\r\n");
+
+        let result = Pop3Client::builder()
+            .xoauth2("tim", "token")
+            .connect_stream(stream);
+        let Err(Pop3Error::AuthError(actual)) = result else {
+            panic!("expected an auth error");
+        };
+
+        assert_eq!(actual.message, "XOAUTH2 login failed: {\"status\":\"401\"}".to_string());
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "AUTH XOAUTH2 dXNlcj10aW0BYXV0aD1CZWFyZXIgdG9rZW4BAQ==\r\n\r\nQUIT\r\n".to_string());
+    }
+
+    #[test]
+    fn test_connect_gmail_oauth_requires_xoauth2() {
+        let result = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect(Pop3Connection::gmail_oauth());
+        let Err(Pop3Error::AuthError(actual)) = result else {
+            panic!("expected an auth error");
+        };
+
+        assert_eq!(actual.message, "pop.gmail.com requires XOAUTH2 authentication".to_string());
+    }
+
+    #[test]
+    fn test_list_including_deleted() {
+        let (mut client, _) = mock_client("+OK\r\n1 120\r\n2 200\r\n.\r\n+OK message 1 deleted\r\n+OK\r\n2 200\r\n.\r\n");
+
+        client.list().unwrap();
+        client.delete(1).unwrap();
+        let actual: Vec<(u32, u64, bool)> = client.list_including_deleted().unwrap()
+            .into_iter()
+            .map(|(message, deleted)| (message.message_id, message.size, deleted))
+            .collect();
+
+        assert_eq!(actual, vec![(1, 120, true), (2, 200, false)]);
+    }
+
+    #[test]
+    fn test_list_including_deleted_after_list_last() {
+        let (mut client, _) = mock_client("+OK\r\n1 120\r\n2 200\r\n.\r\n+OK message 2 deleted\r\n+OK\r\n1 120\r\n.\r\n");
+
+        client.list_last(1).unwrap();
+        client.delete(2).unwrap();
+        let actual: Vec<(u32, u64, bool)> = client.list_including_deleted().unwrap()
+            .into_iter()
+            .map(|(message, deleted)| (message.message_id, message.size, deleted))
+            .collect();
+
+        assert_eq!(actual, vec![(1, 120, false), (2, 200, true)]);
+    }
+
+    #[test]
+    fn test_transaction_resets_when_dropped() {
+        let (mut client, written) = mock_client("+OK message 1 deleted\r\n+OK\r\n");
+
+        {
+            let mut transaction = client.transaction().unwrap();
+            transaction.delete(1).unwrap();
+        }
+
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "DELE 1\r\nRSET\r\n".to_string());
+        assert!(client.transaction().is_ok());
+    }
+
+    #[test]
+    fn test_transaction_refused_when_messages_are_marked_as_deleted() {
+        let (mut client, written) = mock_client("+OK message 1 deleted\r\n");
+
+        client.delete(1).unwrap();
+        let actual = client.transaction();
+
+        assert!(actual.is_err());
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "DELE 1\r\n".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_with_hash() {
+        let (mut client, _) = mock_client("+OK\r\nSubject: test\r\n\r\nHello\r\n.\r\n");
+
+        let (message, hash) = client.retrieve_with_hash(1).unwrap();
+        let hex: String = hash.iter().map(|b| format!("{b:02x}")).collect();
+
+        assert_eq!(message.data, "Subject: test\r\n\r\nHello\r\n".to_string());
+        assert_eq!(hex, "850f39907091309da6bbf1c9efe6d027195f2b7cc55754ef2b46a39964275a52".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_unstuffs_lines_for_every_accessor() {
+        let message = "+OK\r\n..X-Dots: yes\r\n\r\n..line\r\n.\r\n";
+        let (mut client, _) = mock_client(&message.repeat(3));
+        let mut written = Vec::new();
+
+        client.retrieve(1, &mut written).unwrap();
+        let body = client.retrieve_body(1).unwrap();
+        let header = client.retrieve_as_string(1).unwrap().header(".X-Dots");
+
+        assert_eq!(written, b".X-Dots: yes\r\n\r\n.line\r\n");
+        assert_eq!(body, ".line\r\n".to_string());
+        assert_eq!(header, Some("yes".to_string()));
+    }
+
+    #[test]
+    fn test_retrieve_with_hash_is_over_unstuffed_data() {
+        let (mut client, _) = mock_client("+OK\r\nSubject: test\r\n\r\n..Hello\r\n.\r\n");
+
+        let (_, actual) = client.retrieve_with_hash(1).unwrap();
+        let expected: [u8; 32] = Sha256::digest(b"Subject: test\r\n\r\n.Hello\r\n").into();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_export_mbox() {
+        let (mut client, written) = mock_client("+OK\r\n1 20\r\n2 20\r\n.\r\n+OK\r\nSubject: one\r\n.\r\n+OK\r\nSubject: two\r\n.\r\n");
+        let mut mbox = Vec::new();
+
+        let actual = client.export_mbox(&mut mbox).unwrap();
+
+        assert_eq!(actual, 2);
+        assert_eq!(String::from_utf8(mbox).unwrap().matches("From MAILER-DAEMON").count(), 2);
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "LIST\r\nRETR 1\r\nRETR 2\r\n".to_string());
+    }
+
+    #[test]
+    fn test_save_all_to_dir_unstuffs_lines() {
+        let dir = std::env::temp_dir().join(format!("pop3-test-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (mut client, _) = mock_client("+OK\r\n1 20\r\n.\r\n+OK\r\nSubject: dots\r\n\r\n..hidden\r\n.\r\n");
+
+        let actual = client.save_all_to_dir_with_line_ending(&dir, LineEnding::Lf).unwrap();
+        let saved = std::fs::read_to_string(dir.join("1.eml")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(actual, 1);
+        assert_eq!(saved, "Subject: dots\n\n.hidden\n".to_string());
+    }
+
+    #[test]
+    fn test_export_mbox_empty_mailbox() {
+        let (mut client, _) = mock_client("+OK\r\n.\r\n");
+        let mut mbox = Vec::new();
+
+        let actual = client.export_mbox(&mut mbox).unwrap();
+
+        assert_eq!(actual, 0);
+        assert!(mbox.is_empty());
+    }
+
+    #[test]
+    fn test_tls_info_for_plaintext_connection() {
+        let (port, server) = fake_server("+OK ready\r\n", vec!["+OK bye\r\n"]);
+
+        let client = Pop3Client::builder()
+            .no_login()
+            .connect(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext))
+            .unwrap();
+        let actual = client.tls_info();
+        drop(client);
+
+        assert!(actual.is_none());
+        assert_eq!(server.join().unwrap(), vec!["QUIT"]);
+    }
+
+    #[test]
+    fn test_list_id_for_deleted_message() {
+        let (mut client, written) = mock_client("+OK message 2 deleted\r\n-ERR message 3 already deleted\r\n");
+
+        client.delete(2).unwrap();
+        let tracked = client.list_id(2).unwrap_err();
+        let reported = client.list_id(3).unwrap_err();
+
+        assert_eq!(tracked.kind, ListErrorKind::MessageDeleted);
+        assert_eq!(reported.kind, ListErrorKind::MessageDeleted);
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "DELE 2\r\nLIST 3\r\n".to_string());
+    }
+
+    #[test]
+    fn test_timeout_is_default_for_specific_timeouts() {
+        let (port, server) = fake_server("+OK ready\r\n", vec!["+OK bye\r\n"]);
+
+        let client = Pop3Client::builder()
+            .no_login()
+            .timeout(Duration::from_secs(30))
+            .read_timeout(Duration::from_secs(5))
+            .connect(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext))
+            .unwrap();
+        let socket = client.socket.as_ref().unwrap();
+        let read_timeout = socket.read_timeout().unwrap();
+        let write_timeout = socket.write_timeout().unwrap();
+        drop(client);
+
+        assert_eq!(read_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(write_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(server.join().unwrap(), vec!["QUIT"]);
+    }
+
+    #[test]
+    fn test_no_timeouts_clears_every_timeout() {
+        let builder = Pop3Client::builder()
+            .no_login()
+            .timeout(Duration::from_secs(30))
+            .connect_timeout(Duration::from_secs(5))
+            .read_timeout(Duration::from_secs(5))
+            .write_timeout(Duration::from_secs(5))
+            .no_timeouts();
+
+        assert_eq!(builder.effective_connect_timeout(), None);
+        assert_eq!(builder.read_timeout, None);
+        assert_eq!(builder.write_timeout, None);
+    }
+
+    #[test]
+    fn test_top_all() {
+        let (mut client, written) = mock_client("+OK\r\n1 20\r\n2 20\r\n.\r\n+OK\r\nSubject: one\r\n.\r\n+OK\r\nSubject: two\r\n.\r\n");
+
+        let actual = client.top_all(0).unwrap();
+
+        assert_eq!(actual.iter().map(|top| top.message_id).collect::<Vec<u32>>(), vec![1, 2]);
+        assert_eq!(actual[1].data, "Subject: two\r\n".to_string());
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "LIST\r\nTOP 1 0\r\nTOP 2 0\r\n".to_string());
+    }
+
+    #[test]
+    fn test_top_all_not_supported() {
+        let (mut client, written) = mock_client("+OK\r\n1 20\r\n2 20\r\n.\r\n-ERR unknown command\r\n");
+
+        let actual = client.top_all(0).unwrap_err();
+
+        assert_eq!(actual.kind, TopErrorKind::NotSupported);
+        assert_eq!(actual.message, "server does not support TOP: unknown command".to_string());
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "LIST\r\nTOP 1 0\r\n".to_string());
+    }
+
+    #[test]
+    fn test_top_all_not_advertised() {
+        let (mut client, written) = mock_client("");
+        client.capabilities = Some("USER".to_string().try_into().unwrap());
+
+        let actual = client.top_all(0).unwrap_err();
+
+        assert_eq!(actual.kind, TopErrorKind::NotSupported);
+        assert_eq!(actual.message, "TOP is not supported by the server".to_string());
+        assert!(written.take().is_empty());
+    }
+
+    #[test]
+    fn test_retrieve_into_clears_and_reuses_the_buffer() {
+        let (mut client, _) = mock_client("+OK 20 octets\r\nSubject: first\r\n..hi\r\n.\r\n+OK\r\nshort\r\n.\r\n-ERR no such message\r\n");
+        let mut buffer = Vec::with_capacity(64);
+
+        client.retrieve_into(1, &mut buffer).unwrap();
+        assert_eq!(buffer, b"Subject: first\r\n.hi\r\n");
+
+        client.retrieve_into(2, &mut buffer).unwrap();
+        assert_eq!(buffer, b"short\r\n");
+        assert!(buffer.capacity() >= 64);
+
+        let err = client.retrieve_into(3, &mut buffer).unwrap_err();
+        assert_eq!(err.message, "no such message");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_invoke_rejects_embedded_line_breaks() {
+        let (mut client, written) = mock_client("");
+
+        let err = client.invoke("USER me\r\nDELE 1").unwrap_err();
+        let lone_lf = client.invoke("USER me\nDELE 1");
+        drop(client);
+
+        assert_eq!(err, "command contains a line break, which is not allowed");
+        assert!(lone_lf.is_err());
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "QUIT\r\n".to_string());
+    }
+
+    #[test]
+    fn test_drain_keeps_the_message_the_handler_failed_on() {
+        let (mut client, written) = mock_client("+OK\r\n1 5\r\n2 5\r\n.\r\n+OK\r\none\r\n.\r\n+OK\r\n+OK\r\ntwo\r\n.\r\n");
+        let mut handled = vec![];
+
+        let result = client.drain(|message| {
+            handled.push(message.data.clone());
+            if message.data == "two\r\n" {
+                return Err(Pop3Error::from(RetrieveError::from("could not store message".to_string())));
+            }
+            Ok(())
+        });
+        drop(client);
+
+        assert!(matches!(result, Err(Pop3Error::RetrieveError(RetrieveError { message, .. })) if message == "could not store message"));
+        assert_eq!(handled, vec!["one\r\n".to_string(), "two\r\n".to_string()]);
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "LIST\r\nRETR 1\r\nDELE 1\r\nRETR 2\r\nQUIT\r\n".to_string());
+    }
+
+    #[test]
+    fn test_message_numbers() {
+        let (mut client, _) = mock_client("+OK\r\n1 120\r\n3 200\r\n.\r\n");
+
+        let numbers = client.message_numbers().unwrap();
+
+        assert_eq!(numbers, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_connect_with_empty_root_store() {
+        let (port, server) = fake_server("+OK ready\r\n", vec![]);
+
+        let result = Pop3Client::builder()
+            .root_store(RootCertStore::empty())
+            .username("tim")
+            .password("tanstaaf")
+            .connect(Pop3Connection::custom("127.0.0.1", port, Security::StartTls));
+        let Err(Pop3Error::ConnectionError(actual)) = result else {
+            panic!("expected a connection error");
+        };
+
+        assert_eq!(actual.message, "the root store passed to root_store is empty".to_string());
+        assert!(server.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_connect_retrying_does_not_retry_rejected_credentials() {
+        let (port, server) = fake_server("+OK ready\r\n", vec!["+OK\r\n", "-ERR invalid password\r\n"]);
+
+        let result = Pop3Client::builder()
+            .username("tim")
+            .password("wrong")
+            .connect_retrying(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext), 3, Duration::ZERO);
+
+        assert!(matches!(result, Err(Pop3Error::AuthError(_))));
+        assert_eq!(server.join().unwrap(), vec!["USER tim", "PASS wrong"]);
+    }
+
+    #[test]
+    fn test_connect_retrying_waits_between_attempts() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let start = Instant::now();
+
+        let result = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect_retrying(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext), 3, Duration::from_millis(20));
+
+        assert!(matches!(result, Err(Pop3Error::ConnectionError(ConnectionError { kind: ConnectionErrorKind::Transport, .. }))));
+        // 20 ms before the second attempt, 40 ms before the third
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn test_connect_retrying_retries_connection_closed_during_login() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            // the first connection closes right after USER, the second one logs in
+            let (mut dropped, _) = listener.accept().unwrap();
+            dropped.write_all(b"+OK ready\r\n").unwrap();
+            BufReader::new(dropped).read_line(&mut String::new()).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"+OK ready\r\n").unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            for response in ["+OK\r\n", "+OK\r\n", "+OK bye\r\n"] {
+                reader.read_line(&mut String::new()).unwrap();
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let result = Pop3Client::builder()
+            .username("tim")
+            .password("tanstaaf")
+            .connect_retrying(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext), 2, Duration::ZERO);
+
+        assert_eq!(result.unwrap().auth_method(), Some(AuthMethod::UserPass));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_connect_retrying_does_not_retry_configuration_errors() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let result = Pop3Client::builder()
+            .root_store(RootCertStore::empty())
+            .username("tim")
+            .password("tanstaaf")
+            .connect_retrying(Pop3Connection::custom("127.0.0.1", port, Security::StartTls), 3, Duration::ZERO);
+        listener.set_nonblocking(true).unwrap();
+        let attempts = std::iter::from_fn(|| listener.accept().ok()).count();
+
+        assert!(matches!(result, Err(Pop3Error::ConnectionError(ConnectionError { kind: ConnectionErrorKind::Other, .. }))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_connect_retrying_zero_attempts() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let result = Pop3Client::builder()
+            .no_login()
+            .connect_retrying(Pop3Connection::custom("127.0.0.1", port, Security::Plaintext), 0, Duration::ZERO);
+
+        assert!(matches!(result, Err(Pop3Error::ConnectionError(ConnectionError { message, .. })) if message == "connect_retrying needs at least one attempt"));
+    }
+
+    fn written_by(server: &str, commands: impl FnOnce(&mut Pop3Client<MockStream>)) -> String {
+        let (mut client, written) = mock_client(server);
+        commands(&mut client);
+        drop(client);
+        String::from_utf8(written.take()).unwrap()
+    }
+
+    #[test]
+    fn test_commands_without_arguments_on_the_wire() {
+        assert_eq!(written_by("+OK 1 5\r\n", |c| { c.stat().unwrap(); }), "STAT\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\n1 5\r\n.\r\n", |c| { c.list().unwrap(); }), "LIST\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\n", |c| c.reset().unwrap()), "RSET\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\n", |c| c.noop().unwrap()), "NOOP\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\n1 abc\r\n.\r\n", |c| { c.uidl().unwrap(); }), "UIDL\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\nTOP\r\n.\r\n", |c| { c.capa().unwrap(); }), "CAPA\r\nQUIT\r\n");
+    }
+
+    #[test]
+    fn test_commands_with_arguments_on_the_wire() {
+        assert_eq!(written_by("+OK 2 5\r\n", |c| { c.list_id(2).unwrap(); }), "LIST 2\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\nhi\r\n.\r\n", |c| { c.retrieve_as_string(3).unwrap(); }), "RETR 3\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\n", |c| c.delete(3).unwrap()), "DELE 3\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK 4 abc\r\n", |c| { c.uidl_with_id(4).unwrap(); }), "UIDL 4\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\nSubject: hi\r\n.\r\n", |c| { c.top(5, 10).unwrap(); }), "TOP 5 10\r\nQUIT\r\n");
+        assert_eq!(written_by("+OK\r\n+OK\r\n", |c| { c.delete_many(&[10, 200]).unwrap(); }), "DELE 10\r\nDELE 200\r\nQUIT\r\n");
+    }
+
+    #[test]
+    fn test_list_empty_mailbox() {
+        let (mut client, _) = mock_client("+OK 0 messages\r\n.\r\n");
+
+        let list = client.list().unwrap();
+
+        assert!(list.messages.is_empty());
+    }
+
+    #[test]
+    fn test_capabilities_runs_capa_once() {
+        let (mut client, written) = mock_client("+OK Capability list follows\r\nTOP\r\nUIDL\r\n.\r\n");
+
+        assert!(client.cached_capabilities().is_none());
+        let first = client.capabilities().unwrap().supports("TOP");
+        let second = client.capabilities().unwrap().supports("UIDL");
+        let cached = client.cached_capabilities().unwrap().capabilities.len();
+        drop(client);
+
+        assert!(first && second);
+        assert_eq!(cached, 2);
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "CAPA\r\nQUIT\r\n".to_string());
+    }
+
+    #[test]
+    fn test_list_with_status_line_is_not_an_item() {
+        let (mut client, _) = mock_client("+OK 2 messages (320 octets)\r\n1 120\r\n2 200\r\n.\r\n");
+
+        let list = client.list().unwrap();
+
+        assert_eq!(list.messages.len(), 2);
+        assert_eq!((list.messages[0].message_id, list.messages[0].size), (1, 120));
+        assert_eq!((list.messages[1].message_id, list.messages[1].size), (2, 200));
+    }
+
+    struct TokenAuth(&'static str);
+
+    impl AuthMechanism for TokenAuth {
+        fn authenticate(&self, session: &mut AuthSession<'_>) -> Result<(), Pop3Error> {
+            session.send("AUTH X-TOKEN")?;
+            let challenge = session.read_continuation()?;
+            session.send_secret(&format!("{challenge}:{}", self.0))?;
+            session.read_response()?;
+            Ok(())
+        }
+
+        fn secret(&self) -> Option<&str> {
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn test_connect_with_custom_auth_mechanism() {
+        let (stream, written) = mock_stream("+OK ready\r\n+ nonce\r\n+OK logged in\r\n");
+
+        let client = Pop3Client::builder()
+            .auth(TokenAuth("s3cret"))
+            .connect_stream(stream)
+            .unwrap();
+
+        assert_eq!(client.auth_method(), Some(AuthMethod::Custom));
+        assert_eq!(String::from_utf8(written.take()).unwrap(), "AUTH X-TOKEN\r\nnonce:s3cret\r\n".to_string());
+    }
+
+    #[test]
+    fn test_custom_auth_mechanism_rejection_is_redacted() {
+        let (stream, _) = mock_stream("+OK ready\r\n+ nonce\r\n-ERR bad token s3cret\r\n");
+
+        let result = Pop3Client::builder()
+            .auth(TokenAuth("s3cret"))
+            .connect_stream(stream);
+        let Err(Pop3Error::AuthError(actual)) = result else {
+            panic!("expected an auth error");
+        };
+
+        assert_eq!(actual.message, "bad token ***".to_string());
+    }
+
+    #[test]
+    fn test_oauth_preset_accepts_xoauth2_mechanism() {
+        let builder = Pop3Client::builder().auth(XOAuth2::new("tim@gmail.com", "token"));
+
+        assert!(builder.check_required_auth(&Pop3Connection::gmail_oauth()).is_ok());
+        assert!(Pop3Client::builder().auth(UserPass::new("tim", "pass")).check_required_auth(&Pop3Connection::gmail_oauth()).is_err());
+    }
+
+    #[test]
+    fn test_probe_reads_greeting_without_login() {
+        let (port, server) = fake_server("+OK POP3 ready\r\n", vec!["+OK bye\r\n"]);
+
+        let greeting = Pop3Connection::custom("127.0.0.1", port, Security::Plaintext).probe(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(greeting, "POP3 ready".to_string());
+        assert_eq!(server.join().unwrap(), vec!["QUIT"]);
+    }
+
+    #[test]
+    fn test_probe_refused_greeting() {
+        let (port, _) = fake_server("-ERR maintenance\r\n", vec![]);
+
+        let actual = Pop3Connection::custom("127.0.0.1", port, Security::Plaintext).probe(Duration::from_secs(5)).unwrap_err();
+
+        assert_eq!(actual.kind, ConnectionErrorKind::ServerRefused);
+    }
 }
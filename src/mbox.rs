@@ -0,0 +1,42 @@
+use std::io::Write;
+
+use crate::responses::RetrieveResponse;
+
+// POP3 does not tell us when a message arrived, so every separator has the same (epoch) date, like many exporters do
+const SEPARATOR: &str = "From MAILER-DAEMON Thu Jan  1 00:00:00 1970\n";
+
+/// Write the message as an mboxrd entry: the `From ` separator, the lines of the message (with LF endings,
+/// and `From ` lines quoted with `>`), and a blank line
+pub(crate) fn write_mbox_entry(message: &RetrieveResponse, writer: &mut impl Write) -> std::io::Result<()> {
+    writer.write_all(SEPARATOR.as_bytes())?;
+    for line in message.lines() {
+        // mboxrd also quotes lines that were already quoted, so readers can undo it by removing one `>`
+        if line.trim_start_matches('>').starts_with("From ") {
+            writer.write_all(b">")?;
+        }
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_mbox_entry_quotes_from_lines() {
+        let message = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\r\n\r\nFrom here\r\n>From there\r\nFromage".to_string(),
+        };
+        let mut actual = Vec::new();
+
+        write_mbox_entry(&message, &mut actual).unwrap();
+
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "From MAILER-DAEMON Thu Jan  1 00:00:00 1970\nSubject: test\n\n>From here\n>>From there\nFromage\n\n".to_string()
+        );
+    }
+}
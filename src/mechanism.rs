@@ -0,0 +1,268 @@
+use crate::auth::{apop_digest, apop_timestamp, cram_md5_response, decode_server_message, sasl_plain_response, supports_sasl, xoauth2_response};
+use crate::errors::{AuthError, ConnectionError, Pop3Error};
+use crate::reader::ResponseError;
+use crate::responses::CapaResponse;
+use crate::transcript::RedactedString;
+use crate::{ApopDigest, AuthMethod};
+
+/// A way of logging in to the server. Pass one to `auth` on the builder, e.g. for a provider-specific flow.
+/// The mechanisms of this crate (`UserPass`, `Apop`, `CramMd5`, `SaslPlain` and `XOAuth2`) implement it as well
+pub trait AuthMechanism: Send + Sync {
+    /// Log in over the session. A rejection should be an `AuthError`, which is what `AuthSession::read_response` returns for `-ERR`
+    fn authenticate(&self, session: &mut AuthSession<'_>) -> Result<(), Pop3Error>;
+
+    /// What `Pop3Client::auth_method` returns after logging in with this mechanism
+    fn method(&self) -> AuthMethod {
+        AuthMethod::Custom
+    }
+
+    /// The secret of this mechanism, which is replaced by `***` in errors and the transcript when the server echoes it back
+    fn secret(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// What the client does for an `AuthSession`, so the session does not depend on the type of stream
+pub(crate) trait SessionIo {
+    fn send_command(&mut self, command: &str, secret: bool) -> Result<(), String>;
+    fn receive_response(&mut self) -> Result<String, ResponseError>;
+    fn receive_continuation(&mut self) -> Result<String, ResponseError>;
+    fn receive_sasl_outcome(&mut self) -> Result<Option<String>, ResponseError>;
+}
+
+// only what the server answered says something about the login, a response that never arrived is a connection problem
+fn login_error(error: ResponseError, on_server_message: impl FnOnce(String) -> Pop3Error) -> Pop3Error {
+    match error {
+        ResponseError::Server(message) => on_server_message(message),
+        error => ConnectionError::from_missing_response(error).into(),
+    }
+}
+
+/// The session with the server while an `AuthMechanism` logs in
+pub struct AuthSession<'a> {
+    io: &'a mut dyn SessionIo,
+    greeting: &'a str,
+    capabilities: Option<&'a CapaResponse>,
+}
+
+impl<'a> AuthSession<'a> {
+    pub(crate) fn new(io: &'a mut dyn SessionIo, greeting: &'a str, capabilities: Option<&'a CapaResponse>) -> AuthSession<'a> {
+        AuthSession { io, greeting, capabilities }
+    }
+
+    /// The greeting of the server, without `+OK`
+    pub fn greeting(&self) -> &str {
+        self.greeting
+    }
+
+    /// The capabilities the server advertised before logging in, if CAPA ran before the login (see `capa_before_login` on the builder)
+    pub fn capabilities(&self) -> Option<&CapaResponse> {
+        self.capabilities
+    }
+
+    /// Send a command, which ends up in the transcript (with the password of PASS and the digest of APOP redacted)
+    pub fn send(&mut self, command: &str) -> Result<(), Pop3Error> {
+        Ok(self.io.send_command(command, false).map_err(ConnectionError::from)?)
+    }
+
+    /// Send a command that consists entirely of secrets, like a SASL response, which stays out of the transcript
+    pub fn send_secret(&mut self, command: &str) -> Result<(), Pop3Error> {
+        Ok(self.io.send_command(command, true).map_err(ConnectionError::from)?)
+    }
+
+    /// Read a single-line response, returning the text after `+OK`. An `-ERR` is an `AuthError`,
+    /// or a `ConnectionError` when the mailbox is locked. Failing to read the response is a `ConnectionError`
+    pub fn read_response(&mut self) -> Result<String, Pop3Error> {
+        self.io.receive_response().map_err(|err| login_error(err, Pop3Error::from_login_message))
+    }
+
+    /// Read a SASL continuation (`+ challenge`), returning the challenge as the server sent it (base64 encoded)
+    pub fn read_continuation(&mut self) -> Result<String, Pop3Error> {
+        self.io
+            .receive_continuation()
+            .map_err(|err| login_error(err, |message| AuthError::from(decode_server_message(message)).into()))
+    }
+
+    /// Read the outcome of a SASL exchange: None for `+OK`, or the (base64 encoded) details of a continuation,
+    /// which some mechanisms send when the login fails
+    pub fn read_sasl_outcome(&mut self) -> Result<Option<String>, Pop3Error> {
+        self.io
+            .receive_sasl_outcome()
+            .map_err(|err| login_error(err, |message| Pop3Error::from_login_message(decode_server_message(message))))
+    }
+
+    // SASL servers may base64 encode the text of their -ERR
+    fn read_sasl_response(&mut self) -> Result<String, Pop3Error> {
+        self.io
+            .receive_response()
+            .map_err(|err| login_error(err, |message| Pop3Error::from_login_message(decode_server_message(message))))
+    }
+}
+
+/// The USER and PASS commands
+pub struct UserPass {
+    user: String,
+    pass: RedactedString,
+}
+
+impl UserPass {
+    pub fn new(user: &str, pass: &str) -> UserPass {
+        UserPass { user: user.to_string(), pass: RedactedString::new(pass) }
+    }
+}
+
+impl AuthMechanism for UserPass {
+    fn authenticate(&self, session: &mut AuthSession<'_>) -> Result<(), Pop3Error> {
+        session.send(&format!("USER {}", self.user))?;
+        session.read_response()?;
+        session.send(&format!("PASS {}", self.pass.expose()))?;
+        session.read_response()?;
+        Ok(())
+    }
+
+    fn method(&self) -> AuthMethod {
+        AuthMethod::UserPass
+    }
+
+    fn secret(&self) -> Option<&str> {
+        Some(self.pass.expose())
+    }
+}
+
+/// The APOP command, with a digest of the timestamp in the greeting and the secret
+pub struct Apop {
+    user: String,
+    secret: RedactedString,
+    digest: ApopDigest,
+}
+
+impl Apop {
+    /// APOP with an MD5 digest, as specified by the RFC
+    pub fn new(user: &str, secret: &str) -> Apop {
+        Apop { user: user.to_string(), secret: RedactedString::new(secret), digest: ApopDigest::default() }
+    }
+
+    /// Use another digest, for servers that support it
+    pub fn digest(self, digest: ApopDigest) -> Apop {
+        Apop { digest, ..self }
+    }
+}
+
+impl AuthMechanism for Apop {
+    fn authenticate(&self, session: &mut AuthSession<'_>) -> Result<(), Pop3Error> {
+        let timestamp = apop_timestamp(session.greeting())
+            .ok_or_else(|| AuthError::from("server greeting does not contain an APOP timestamp".to_string()))?;
+        let command = format!("APOP {} {}", self.user, apop_digest(timestamp, self.secret.expose(), self.digest));
+        session.send(&command)?;
+        session.read_response()?;
+        Ok(())
+    }
+
+    fn method(&self) -> AuthMethod {
+        AuthMethod::Apop
+    }
+
+    fn secret(&self) -> Option<&str> {
+        Some(self.secret.expose())
+    }
+}
+
+/// SASL CRAM-MD5
+pub struct CramMd5 {
+    user: String,
+    secret: RedactedString,
+}
+
+impl CramMd5 {
+    pub fn new(user: &str, secret: &str) -> CramMd5 {
+        CramMd5 { user: user.to_string(), secret: RedactedString::new(secret) }
+    }
+}
+
+impl AuthMechanism for CramMd5 {
+    fn authenticate(&self, session: &mut AuthSession<'_>) -> Result<(), Pop3Error> {
+        session.send("AUTH CRAM-MD5")?;
+        let challenge = session.read_continuation()?;
+        let response = cram_md5_response(&self.user, self.secret.expose(), &challenge).map_err(AuthError::from)?;
+        session.send_secret(&response)?;
+        session.read_sasl_response()?;
+        Ok(())
+    }
+
+    fn method(&self) -> AuthMethod {
+        AuthMethod::CramMd5
+    }
+
+    fn secret(&self) -> Option<&str> {
+        Some(self.secret.expose())
+    }
+}
+
+/// SASL PLAIN
+pub struct SaslPlain {
+    user: String,
+    pass: RedactedString,
+}
+
+impl SaslPlain {
+    pub fn new(user: &str, pass: &str) -> SaslPlain {
+        SaslPlain { user: user.to_string(), pass: RedactedString::new(pass) }
+    }
+}
+
+impl AuthMechanism for SaslPlain {
+    fn authenticate(&self, session: &mut AuthSession<'_>) -> Result<(), Pop3Error> {
+        let response = sasl_plain_response(&self.user, self.pass.expose());
+        // servers that advertise SASL in CAPA follow RFC 5034, which allows sending the credentials with AUTH itself
+        if supports_sasl(session.capabilities(), "PLAIN") {
+            session.send(&format!("AUTH PLAIN {response}"))?;
+        } else {
+            session.send("AUTH PLAIN")?;
+            session.read_continuation()?;
+            session.send_secret(&response)?;
+        }
+        session.read_sasl_response()?;
+        Ok(())
+    }
+
+    fn method(&self) -> AuthMethod {
+        AuthMethod::SaslPlain
+    }
+
+    fn secret(&self) -> Option<&str> {
+        Some(self.pass.expose())
+    }
+}
+
+/// SASL XOAUTH2, with an OAuth 2.0 access token
+pub struct XOAuth2 {
+    user: String,
+    token: RedactedString,
+}
+
+impl XOAuth2 {
+    pub fn new(user: &str, token: &str) -> XOAuth2 {
+        XOAuth2 { user: user.to_string(), token: RedactedString::new(token) }
+    }
+}
+
+impl AuthMechanism for XOAuth2 {
+    fn authenticate(&self, session: &mut AuthSession<'_>) -> Result<(), Pop3Error> {
+        session.send_secret(&format!("AUTH XOAUTH2 {}", xoauth2_response(&self.user, self.token.expose())))?;
+        if let Some(details) = session.read_sasl_outcome()? {
+            // the server expects an empty response to the details, before it fails the command with -ERR
+            session.send_secret("")?;
+            let _ = session.read_response();
+            return Err(AuthError::from(format!("XOAUTH2 login failed: {}", decode_server_message(details))).into());
+        }
+        Ok(())
+    }
+
+    fn method(&self) -> AuthMethod {
+        AuthMethod::XOAuth2
+    }
+
+    fn secret(&self) -> Option<&str> {
+        Some(self.token.expose())
+    }
+}
@@ -0,0 +1,43 @@
+use std::io::{Read, Write};
+use std::vec::IntoIter;
+
+use crate::errors::RetrieveError;
+use crate::responses::RetrieveResponse;
+use crate::stream::Pop3Stream;
+use crate::Pop3Client;
+
+/// Iterator that retrieves the messages one by one as it advances, so only one message is in memory at a time.
+/// The message ids are listed when the iterator is created
+pub struct Messages<'a, S: Read + Write = Pop3Stream> {
+    client: &'a mut Pop3Client<S>,
+    message_ids: IntoIter<u32>,
+}
+
+impl<'a, S: Read + Write> Messages<'a, S> {
+    pub(crate) fn new(client: &'a mut Pop3Client<S>, message_ids: Vec<u32>) -> Messages<'a, S> {
+        Messages {
+            client,
+            message_ids: message_ids.into_iter(),
+        }
+    }
+
+    /// The client, for running other commands in between, like deleting a message after processing it
+    pub fn client(&mut self) -> &mut Pop3Client<S> {
+        self.client
+    }
+}
+
+impl<S: Read + Write> Iterator for Messages<'_, S> {
+    type Item = Result<RetrieveResponse, RetrieveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let message_id = self.message_ids.next()?;
+        Some(self.client.retrieve_as_string(message_id))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.message_ids.size_hint()
+    }
+}
+
+impl<S: Read + Write> ExactSizeIterator for Messages<'_, S> {}
@@ -0,0 +1,29 @@
+use std::net::TcpStream;
+
+use native_tls::{TlsConnector, TlsStream};
+
+use crate::errors::{ConnectionError, Pop3Error};
+use crate::stream::Security;
+use crate::{connect_tcp, Pop3Client, Pop3ClientBuilder, Pop3ClientBuilderConnect, Pop3Connection};
+
+impl Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+    /// Connect to the POP3 server like `connect`, but with the TLS implementation of the platform (OpenSSL on Linux)
+    /// instead of rustls. Only implicit TLS is supported. Since `native-tls` verifies the server with the certificates
+    /// of the platform, `root_certificates` and `root_store` have no effect
+    pub fn connect_native_tls(self, connection: Pop3Connection) -> Result<Pop3Client<TlsStream<TcpStream>>, Pop3Error> {
+        self.check_required_auth(&connection)?;
+        let Pop3Connection { host, port, security, .. } = connection;
+        if security != Security::ImplicitTls {
+            return Err(ConnectionError::from(format!("native-tls connections only support implicit TLS, not {security:?}")).into());
+        }
+        let port = self.port.unwrap_or(port);
+        let tcp_stream = connect_tcp(host, port, self.effective_connect_timeout())?;
+        self.configure_socket(&tcp_stream)?;
+        let socket = tcp_stream.try_clone().ok();
+        let stream = TlsConnector::new()
+            .map_err(ConnectionError::from)?
+            .connect(host, tcp_stream)
+            .map_err(|err| ConnectionError::from(format!("could not set up client connection: {err}")))?;
+        self.start_session(stream, socket, Some(Security::ImplicitTls), |_| Ok(()))
+    }
+}
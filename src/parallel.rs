@@ -0,0 +1,52 @@
+use std::thread;
+
+use crate::errors::Pop3Error;
+use crate::responses::RetrieveResponse;
+use crate::{Pop3Client, Pop3ClientBuilder, Pop3ClientBuilderConnect, Pop3Connection};
+
+impl Pop3ClientBuilder<Pop3ClientBuilderConnect> {
+    /// Retrieve all messages using up to number_of_connections parallel sessions, each retrieving a part of the messages.
+    /// The messages are returned in order of their message id.
+    ///
+    /// Many servers lock the mailbox to a single session. When additional sessions cannot be opened, the messages
+    /// are retrieved over the sessions that could, which means a single session for those servers
+    pub fn retrieve_all_parallel(self, connection: Pop3Connection, number_of_connections: usize) -> Result<Vec<RetrieveResponse>, Pop3Error> {
+        let mut first = self.clone().connect(connection)?;
+        let message_ids: Vec<u32> = first.list()?.messages
+            .iter()
+            .map(|m| m.message_id)
+            .collect();
+
+        let mut clients = vec![first];
+        // stop at the first failure, which is probably the server refusing a second session
+        for _ in 1..number_of_connections.min(message_ids.len()) {
+            match self.clone().connect(connection) {
+                Ok(client) => clients.push(client),
+                Err(_) => break,
+            }
+        }
+
+        let chunk_size = message_ids.len().div_ceil(clients.len()).max(1);
+        let results: Vec<Result<Vec<RetrieveResponse>, Pop3Error>> = thread::scope(|scope| {
+            let handles: Vec<_> = clients.iter_mut()
+                .zip(message_ids.chunks(chunk_size))
+                .map(|(client, ids)| scope.spawn(move || retrieve_each(client, ids)))
+                .collect();
+            handles.into_iter()
+                .map(|h| h.join().unwrap_or_else(|_| Err(Pop3Error::RetrieveError("a retrieving thread panicked".to_string().into()))))
+                .collect()
+        });
+
+        let mut messages = Vec::with_capacity(message_ids.len());
+        for result in results {
+            messages.extend(result?);
+        }
+        Ok(messages)
+    }
+}
+
+fn retrieve_each(client: &mut Pop3Client, message_ids: &[u32]) -> Result<Vec<RetrieveResponse>, Pop3Error> {
+    message_ids.iter()
+        .map(|id| client.retrieve_as_string(*id).map_err(Pop3Error::from))
+        .collect()
+}
\ No newline at end of file
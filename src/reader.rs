@@ -1,74 +1,287 @@
-use std::io::Read;
+use std::io::{BufRead, ErrorKind};
+use std::string::FromUtf8Error;
 
-const READ_BUFFER_SIZE: usize = 512;
-const READ_ALL_BUFFER_SIZE: usize = 2048; // bigger calls can probably use a bigger buffer? depends on how much data we get in one go though
+const NEWLINE: u8 = 10;
+const PERIOD: u8 = 46;
+const CARRIAGE_RETURN: u8 = 13;
+
+const OK_RESPONSE_START: &str = "+OK";
+const ERR_RESPONSE_START: &str = "-ERR";
+const CONTINUATION_RESPONSE_START: &str = "+";
+// decoded from EF BB BF, which some servers (or messages) put before the content
+const BYTE_ORDER_MARK: char = '\u{FEFF}';
+const BYTE_ORDER_MARK_BYTES: [u8; 3] = [0xEF, 0xBB, 0xBF];
+pub const REFUSED_GREETING_START: &str = "server refused connection: ";
+
+/// Why a single-line response could not be read, so a server that rejects something can be told apart from a broken connection
+#[derive(Debug, PartialEq)]
+pub enum ResponseError {
+    /// The server answered, but not with `+OK`: the text of an `-ERR`, or a description of the unexpected response
+    Server(String),
+    /// No (complete) response arrived, e.g. because the connection closed or the read timed out
+    Transport(String),
+    /// Reading failed for a reason other than the connection, e.g. the TLS handshake rejected the certificate of the server
+    Stream(String),
+}
 
-const PERIOD_SURROUNDED_BY_NEWLINE: [u8; 3] = [10, 46, 10];
-const PERIOD_SURROUNDED_BY_CARRIAGE_RETURN_AND_NEWLINE: [u8; 5] = [13, 10, 46, 13, 10];
+impl From<ResponseError> for String {
+    fn from(value: ResponseError) -> Self {
+        match value {
+            ResponseError::Server(message) | ResponseError::Transport(message) | ResponseError::Stream(message) => message,
+        }
+    }
+}
 
-const ZERO: u8 = 0;
-const NEWLINE: u8 = 10;
-const HYPHEN: u8 = 45;
+pub fn read_continuation_response(reader: &mut impl BufRead) -> Result<String, ResponseError> {
+    let response = read(reader)?;
+    // a continuation is a plus, optionally followed by a space and (base64) data, but not `+OK`
+    if response.starts_with(CONTINUATION_RESPONSE_START) && !response.starts_with(OK_RESPONSE_START) {
+        Ok(response.replacen(CONTINUATION_RESPONSE_START, "", 1).trim().to_string())
+    } else {
+        translate_string_response(response)
+            .and_then(|ok| Err(format!("expected a continuation, got: {OK_RESPONSE_START} {ok}")))
+            .map_err(ResponseError::Server)
+    }
+}
+
+/// Read the outcome of a SASL exchange, which is either `+OK`, or (for XOAUTH2) a continuation with the details of the failure
+pub fn read_sasl_outcome(reader: &mut impl BufRead) -> Result<Option<String>, ResponseError> {
+    let response = read(reader)?;
+    if response.starts_with(CONTINUATION_RESPONSE_START) && !response.starts_with(OK_RESPONSE_START) {
+        Ok(Some(response.replacen(CONTINUATION_RESPONSE_START, "", 1).trim().to_string()))
+    } else {
+        translate_string_response(response).map(|_| None).map_err(ResponseError::Server)
+    }
+}
+
+pub fn read_response(reader: &mut impl BufRead) -> Result<String, ResponseError> {
+    let response = read(reader)?;
+    translate_string_response(response).map_err(ResponseError::Server)
+}
+
+pub fn read_greeting(reader: &mut impl BufRead) -> Result<String, ResponseError> {
+    let mut greeting = read(reader)?;
+    // some servers send blank lines before the actual greeting
+    while greeting.trim().is_empty() {
+        greeting = read(reader)?;
+    }
+    let greeting = greeting.trim_start().to_string();
+    if greeting.starts_with(ERR_RESPONSE_START) {
+        return translate_string_response(greeting).map_err(|message| ResponseError::Server(format!("{REFUSED_GREETING_START}{message}")));
+    }
+    translate_string_response(greeting.clone())
+        .map_err(|_| ResponseError::Server(format!("invalid greeting from server: {}", greeting.trim())))
+}
+
+/// The RFC 2449 response code at the start of a message, e.g. `SYS/PERM` for `[SYS/PERM] account disabled`
+pub fn response_code(message: &str) -> Option<String> {
+    let code = message.strip_prefix('[')?.split_once(']')?.0;
+    Some(code.to_string()).filter(|code| !code.is_empty() && !code.contains(char::is_whitespace))
+}
 
-const OK_RESPONSE_START: &'static str = "+OK";
-const ERR_RESPONSE_START: &'static str = "-ERR";
+/// Error while reading a response, with the bytes that did arrive before the error happened
+pub struct ReadError {
+    pub message: String,
+    pub partial: Vec<u8>,
+}
+
+impl From<FromUtf8Error> for ReadError {
+    fn from(value: FromUtf8Error) -> Self {
+        ReadError {
+            message: format!("could not decode response as UTF-8: {}", value.utf8_error()),
+            partial: value.into_bytes(),
+        }
+    }
+}
+
+/// How multiline responses are read
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MultilineOptions {
+    /// Accept a terminating period glued to the last line when nothing follows it, see `Pop3ClientBuilder::lenient_terminator`
+    pub lenient_terminator: bool,
+    /// Fail once the content has more lines than this
+    pub max_lines: Option<usize>,
+}
 
-pub fn read_response(reader: &mut impl Read) -> Result<String, String> {
-    let response = read(reader);
-    translate_string_response(response)
+pub fn read_multi_response(reader: &mut impl BufRead, options: MultilineOptions) -> Result<String, String> {
+    read_multi_response_with_partial(reader, options).map_err(|err| err.message)
 }
 
-pub fn read_multi_response(reader: &mut impl Read) -> Result<String, String> {
-    let response = read_all(reader);
-    translate_string_response(response)
+/// The content of a multiline response, without the status line: the text after `+OK` (like `2 messages (320 octets)`)
+/// is not part of the content. Only a byte order mark is removed, whitespace (like the final line ending) is kept
+pub fn read_multi_response_with_partial(reader: &mut impl BufRead, options: MultilineOptions) -> Result<String, ReadError> {
+    let response = read_all(reader, options)?;
+    let start = content_start(response.as_bytes()).map_err(|message| ReadError {
+        message,
+        partial: vec![],
+    })?;
+    Ok(response[start..].to_string())
 }
 
-fn translate_string_response(response: String) -> Result<String, String> {
-    if response.starts_with(OK_RESPONSE_START) {
-        Ok(response.replace(OK_RESPONSE_START, "").trim().to_string())
-    } else if response.starts_with(ERR_RESPONSE_START) {
-        Err(response.replace(ERR_RESPONSE_START, "").replace("\r\n", "").trim().to_string())
+/// Read a multiline response into the buffer, which is cleared first, so it can be reused for the next response.
+/// The buffer gets the same content as `read_multi_response_with_partial` returns. On an error, the buffer is empty
+pub fn read_multi_response_into(reader: &mut impl BufRead, buffer: &mut Vec<u8>, options: MultilineOptions) -> Result<(), String> {
+    buffer.clear();
+    match read_all_into(reader, buffer, options).and_then(|_| content_start(buffer)) {
+        Ok(start) => {
+            buffer.drain(..start);
+            Ok(())
+        }
+        Err(message) => {
+            buffer.clear();
+            Err(message)
+        }
+    }
+}
+
+// where the content of a multiline response starts: after the status line, which has to be `+OK`, and any byte order mark
+fn content_start(response: &[u8]) -> Result<usize, String> {
+    let mut start = response.iter().position(|b| *b == NEWLINE).map_or(response.len(), |i| i + 1);
+    translate_string_response(String::from_utf8_lossy(&response[..start]).into_owned())?;
+    while response[start..].starts_with(&BYTE_ORDER_MARK_BYTES) {
+        start += BYTE_ORDER_MARK_BYTES.len();
+    }
+    Ok(start)
+}
+
+// only the status indicator at the start is stripped, the text after it (like `message 3 deleted`) is returned as is
+pub fn translate_string_response(response: String) -> Result<String, String> {
+    if let Some(text) = response.strip_prefix(OK_RESPONSE_START) {
+        Ok(text.trim().trim_start_matches(BYTE_ORDER_MARK).to_string())
+    } else if let Some(text) = response.strip_prefix(ERR_RESPONSE_START) {
+        Err(text.replace("\r\n", "").trim().to_string())
     } else {
         Err(format!("unexpected response: {response}"))
     }
 }
 
-fn read_all(reader: &mut impl Read) -> String {
+// reads line by line, so we stop exactly at the terminating period, and anything after it stays in the buffer of the reader
+fn read_all(reader: &mut impl BufRead, options: MultilineOptions) -> Result<String, ReadError> {
     let mut line_buffer: Vec<u8> = Vec::new();
+    match read_all_into(reader, &mut line_buffer, options) {
+        Ok(()) => Ok(String::from_utf8_lossy(&line_buffer).into_owned()),
+        Err(message) => Err(ReadError {
+            message,
+            partial: line_buffer,
+        }),
+    }
+}
 
-    // we should always get at least 3 u8s, since we have an OK/ERR + \r\n
-    while line_buffer.len() < 3 || (!ends_with_sole_period_and_newline(&line_buffer) && !is_err(&line_buffer)) {
-        let mut byte_buffer = [0; READ_ALL_BUFFER_SIZE];
-        reader.read(&mut byte_buffer).expect("reading to work");
-        line_buffer.extend_from_slice(&byte_buffer);
-        // our buffer might be too long - remove 0 content
-        line_buffer = line_buffer.into_iter().filter(|v| v != &ZERO).collect();
+// appends the response, status line included, to the buffer, with the dot-stuffing of the content undone.
+// With a lenient terminator, a response where the connection closes right after a line ending with a period is complete,
+// for servers that put the terminator on the last line of content instead of its own line. A timeout could just be a slow
+// server, so then only a terminator on its own line that was cut off before its line ending counts.
+// When the content has more than the maximum number of lines, we stop without reading the rest of the response
+fn read_all_into(reader: &mut impl BufRead, line_buffer: &mut Vec<u8>, options: MultilineOptions) -> Result<(), String> {
+    let MultilineOptions { lenient_terminator, max_lines } = options;
+    let response_start = line_buffer.len();
+    let mut content_start = None;
+    let mut content_lines = 0;
+
+    loop {
+        let line_start = line_buffer.len();
+        let bytes_read = match reader.read_until(NEWLINE, line_buffer) {
+            Ok(bytes_read) => bytes_read,
+            Err(err) if lenient_terminator && is_timeout(&err) && is_cut_off_terminator(line_buffer, line_start, content_start) => {
+                line_buffer.truncate(line_start);
+                break;
+            }
+            Err(err) => return Err(format!("could not read response: {err}")),
+        };
+        let line = &line_buffer[line_start..];
+        let is_first_line = line_start == response_start;
+
+        if is_first_line && !is_ok(line) {
+            break;
+        }
+        if !is_first_line && is_sole_period(line) {
+            // the terminator is framing, not content
+            line_buffer.truncate(line_start);
+            break;
+        }
+        if bytes_read == 0 || !line.ends_with(&[NEWLINE]) {
+            // some servers close the connection right after the final period, without a newline
+            if !is_first_line && line == [PERIOD] {
+                line_buffer.truncate(line_start);
+                break;
+            }
+            if lenient_terminator && strip_glued_terminator(line_buffer, content_start) {
+                break;
+            }
+            return Err("connection closed before the end of the response".to_string());
+        }
+        if is_first_line {
+            content_start = Some(line_buffer.len());
+        } else {
+            // the server adds a period to every line that starts with one, so it can't be mistaken for the terminator (RFC 1939)
+            if line.starts_with(&[PERIOD]) {
+                line_buffer.remove(line_start);
+            }
+            content_lines += 1;
+            if let Some(max_lines) = max_lines.filter(|max_lines| content_lines > *max_lines) {
+                return Err(format!("response has more than the maximum of {max_lines} lines"));
+            }
+        }
     }
-    String::from_utf8_lossy(&line_buffer).into_owned()
+    Ok(())
+}
+
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
 }
 
-fn is_err(line_buffer: &Vec<u8>) -> bool {
-    line_buffer[0] == HYPHEN
+// whether the incomplete line is a period (without or with only part of its line ending) right after a complete CRLF line
+fn is_cut_off_terminator(line_buffer: &[u8], line_start: usize, content_start: Option<usize>) -> bool {
+    content_start.is_some()
+        && matches!(line_buffer[line_start..], [PERIOD] | [PERIOD, CARRIAGE_RETURN])
+        && line_buffer[..line_start].ends_with(&[CARRIAGE_RETURN, NEWLINE])
+}
+
+// removes a period (and the line ending after it) at the end of the content, returning whether there was one
+fn strip_glued_terminator(line_buffer: &mut Vec<u8>, content_start: Option<usize>) -> bool {
+    let Some(content_start) = content_start else {
+        return false;
+    };
+    let content = &line_buffer[content_start..];
+    let without_line_ending = content.strip_suffix(b"\r\n")
+        .or_else(|| content.strip_suffix(b"\n"))
+        .unwrap_or(content);
+    match without_line_ending.strip_suffix(&[PERIOD]) {
+        Some(rest) if !rest.is_empty() => {
+            line_buffer.truncate(content_start + rest.len());
+            true
+        }
+        _ => false,
+    }
 }
 
-fn ends_with_sole_period_and_newline(line_buffer: &Vec<u8>) -> bool {
-    let selection = &line_buffer[line_buffer.len() - 3..line_buffer.len()];
-    let second_selection = &line_buffer[line_buffer.len() - 5..line_buffer.len()];
-    selection == PERIOD_SURROUNDED_BY_NEWLINE || second_selection == PERIOD_SURROUNDED_BY_CARRIAGE_RETURN_AND_NEWLINE
+// only a complete `+OK` status line is followed by more lines, so an `-ERR` (or anything unexpected) ends the response
+fn is_ok(line: &[u8]) -> bool {
+    line.starts_with(OK_RESPONSE_START.as_bytes())
 }
 
-fn read(reader: &mut impl Read) -> String {
+// the last line has to be a sole period, ending with either LF or CRLF (independent of how the other lines end)
+fn is_sole_period(line: &[u8]) -> bool {
+    line == [PERIOD, NEWLINE] || line == [PERIOD, CARRIAGE_RETURN, NEWLINE]
+}
+
+fn read(reader: &mut impl BufRead) -> Result<String, ResponseError> {
     let mut line_buffer: Vec<u8> = Vec::new();
 
-    while line_buffer.len() < 2 || line_buffer[line_buffer.len() - 1] != NEWLINE {
-        let mut byte_buffer = [0; READ_BUFFER_SIZE];
-        reader.read(&mut byte_buffer).expect("reading to work");
-        line_buffer.extend_from_slice(&byte_buffer);
-        // our buffer might be too long - remove 0 content
-        // could also optimize by reading shorter stuff for commands that only have something like 'OK' as relevant info
-        line_buffer = line_buffer.into_iter().filter(|v| v != &ZERO).collect();
+    match reader.read_until(NEWLINE, &mut line_buffer) {
+        Ok(_) if line_buffer.ends_with(&[NEWLINE]) => Ok(String::from_utf8_lossy(&line_buffer).into_owned()),
+        Ok(_) => Err(ResponseError::Transport("connection closed before the end of the response".to_string())),
+        Err(err) if is_transport_failure(err.kind()) => Err(ResponseError::Transport(format!("could not read response: {err}"))),
+        Err(err) => Err(ResponseError::Stream(format!("could not read response: {err}"))),
     }
-    String::from_utf8_lossy(&line_buffer).into_owned()
+}
+
+/// Whether the I/O error is about the connection itself (refused, reset, closed or timed out), so trying again may work
+pub fn is_transport_failure(kind: ErrorKind) -> bool {
+    matches!(kind,
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::NotConnected
+        | ErrorKind::BrokenPipe | ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::UnexpectedEof
+        | ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable | ErrorKind::NetworkDown)
 }
 
 #[cfg(test)]
@@ -92,8 +305,7 @@ mod tests {
 
         let actual = read_response(&mut slice);
 
-        assert!(actual.is_err());
-        assert_eq!(actual.err().unwrap(), "an error".to_string());
+        assert_eq!(actual.err().unwrap(), ResponseError::Server("an error".to_string()));
     }
 
     #[test]
@@ -103,28 +315,27 @@ mod tests {
 
         let actual = read_response(&mut slice);
 
-        assert!(actual.is_err());
-        assert_eq!(actual.err().unwrap(), "unexpected response: Something unexpected\n".to_string());
+        assert_eq!(actual.err().unwrap(), ResponseError::Server("unexpected response: Something unexpected\n".to_string()));
     }
 
     #[test]
     fn test_read_multi_response_ok_result_with_carriage_return() {
-        let data = b"+OK Some \nThings \r\n.\r\n";
+        let data = b"+OK\r\nSome \nThings \r\n.\r\n";
         let mut slice: &[u8] = data.as_ref();
 
-        let actual = read_multi_response(&mut slice).unwrap();
+        let actual = read_multi_response(&mut slice, MultilineOptions::default()).unwrap();
 
-        assert_eq!(actual, "Some \nThings \r\n.".to_string());
+        assert_eq!(actual, "Some \nThings \r\n".to_string());
     }
 
     #[test]
     fn test_read_multi_response_ok_result_no_carriage_return() {
-        let data = b"+OK Some \nThings\n.\n";
+        let data = b"+OK\nSome \nThings\n.\n";
         let mut slice: &[u8] = data.as_ref();
 
-        let actual = read_multi_response(&mut slice).unwrap();
+        let actual = read_multi_response(&mut slice, MultilineOptions::default()).unwrap();
 
-        assert_eq!(actual, "Some \nThings\n.".to_string());
+        assert_eq!(actual, "Some \nThings\n".to_string());
     }
 
     #[test]
@@ -132,8 +343,368 @@ mod tests {
         let data = b"-ERR Protocol error \n";
         let mut slice: &[u8] = data.as_ref();
 
-        let actual = read_multi_response(&mut slice);
+        let actual = read_multi_response(&mut slice, MultilineOptions::default());
 
         assert_eq!(actual.err().unwrap(), "Protocol error".to_string());
     }
+
+    #[test]
+    fn test_read_continuation_response_with_challenge() {
+        let data = b"+ PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_continuation_response(&mut slice).unwrap();
+
+        assert_eq!(actual, "PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+".to_string());
+    }
+
+    #[test]
+    fn test_read_continuation_response_err_result() {
+        let data = b"-ERR unsupported mechanism\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_continuation_response(&mut slice);
+
+        assert_eq!(actual.err().unwrap(), ResponseError::Server("unsupported mechanism".to_string()));
+    }
+
+    #[test]
+    fn test_read_greeting_preceded_by_empty_line() {
+        let data = b"\r\n+OK POP3 server ready\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_greeting(&mut slice).unwrap();
+
+        assert_eq!(actual, "POP3 server ready".to_string());
+    }
+
+    #[test]
+    fn test_read_greeting_invalid_contains_full_greeting() {
+        let data = b"* OK IMAP4 ready\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_greeting(&mut slice);
+
+        assert_eq!(actual.err().unwrap(), ResponseError::Server("invalid greeting from server: * OK IMAP4 ready".to_string()));
+    }
+
+    #[test]
+    fn test_read_multi_response_with_partial_when_connection_closes() {
+        let data = b"+OK 120 octets\r\nSubject: cut off";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_multi_response_with_partial(&mut slice, MultilineOptions::default()).err().unwrap();
+
+        assert_eq!(actual.message, "connection closed before the end of the response".to_string());
+        assert_eq!(actual.partial, data.to_vec());
+    }
+
+    #[test]
+    fn test_read_multi_response_ok_result_mixed_line_endings() {
+        let data = b"+OK\r\nSome \r\nThings\r\n.\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_multi_response(&mut slice, MultilineOptions::default()).unwrap();
+
+        assert_eq!(actual, "Some \r\nThings\r\n".to_string());
+    }
+
+    #[test]
+    fn test_read_multi_response_ok_result_lf_line_then_crlf_period() {
+        let data = b"+OK\nSome \nThings\n.\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_multi_response(&mut slice, MultilineOptions::default()).unwrap();
+
+        assert_eq!(actual, "Some \nThings\n".to_string());
+    }
+
+    #[test]
+    fn test_read_multi_response_ok_result_period_followed_by_connection_close() {
+        let data = b"+OK\r\nSome \r\nThings\r\n.";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_multi_response(&mut slice, MultilineOptions::default()).unwrap();
+
+        assert_eq!(actual, "Some \r\nThings\r\n".to_string());
+    }
+
+    #[test]
+    fn test_response_code() {
+        assert_eq!(response_code("[SYS/PERM] account disabled"), Some("SYS/PERM".to_string()));
+        assert_eq!(response_code("[AUTH] invalid"), Some("AUTH".to_string()));
+        assert_eq!(response_code("no code here [AUTH]"), None);
+        assert_eq!(response_code("[not a code] text"), None);
+    }
+
+    #[test]
+    fn test_read_multi_response_keeps_bytes_after_terminator() {
+        let data = b"+OK 1 message\r\n1 120\r\n.\r\n+OK 1 120\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let first = read_multi_response(&mut slice, MultilineOptions::default()).unwrap();
+        let second = read_response(&mut slice).unwrap();
+
+        assert_eq!(first, "1 120\r\n".to_string());
+        assert_eq!(second, "1 120".to_string());
+    }
+
+    #[test]
+    fn test_read_response_when_connection_closes() {
+        let data = b"+OK cut";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_response(&mut slice);
+
+        assert_eq!(actual.err().unwrap(), ResponseError::Transport("connection closed before the end of the response".to_string()));
+    }
+
+    #[test]
+    fn test_read_response_io_errors_by_kind() {
+        struct FailingReader(ErrorKind);
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(self.0, "failed"))
+            }
+        }
+
+        let reset = read_response(&mut std::io::BufReader::new(FailingReader(ErrorKind::ConnectionReset)));
+        let invalid = read_response(&mut std::io::BufReader::new(FailingReader(ErrorKind::InvalidData)));
+
+        assert_eq!(reset.err().unwrap(), ResponseError::Transport("could not read response: failed".to_string()));
+        assert_eq!(invalid.err().unwrap(), ResponseError::Stream("could not read response: failed".to_string()));
+    }
+
+    #[test]
+    fn test_read_greeting_err_is_refused_connection() {
+        let data = b"-ERR [SYS/TEMP] service not available\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_greeting(&mut slice);
+
+        assert_eq!(actual.err().unwrap(), ResponseError::Server("server refused connection: [SYS/TEMP] service not available".to_string()));
+    }
+
+    #[test]
+    fn test_read_multi_response_keeps_last_line_with_literal_dot() {
+        let data = b"+OK\r\nSome text\r\n..\r\n.\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_multi_response(&mut slice, MultilineOptions::default()).unwrap();
+
+        assert_eq!(actual, "Some text\r\n.\r\n".to_string());
+    }
+
+    struct ChunkedReader {
+        data: Vec<u8>,
+        position: usize,
+        chunk_size: usize,
+    }
+
+    impl std::io::Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let end = (self.position + self.chunk_size.min(buf.len())).min(self.data.len());
+            let chunk = &self.data[self.position..end];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            self.position = end;
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn test_read_multi_response_err_result_in_small_chunks() {
+        let data = b"-ERR no such message\r\n+OK still here\r\n".to_vec();
+        let chunked = ChunkedReader { data, position: 0, chunk_size: 2 };
+        let mut reader = std::io::BufReader::with_capacity(2, chunked);
+
+        let actual = read_multi_response(&mut reader, MultilineOptions::default());
+        let next = read_response(&mut reader).unwrap();
+
+        assert_eq!(actual.err().unwrap(), "no such message".to_string());
+        assert_eq!(next, "still here".to_string());
+    }
+
+    #[test]
+    fn test_read_multi_response_unexpected_first_line_ends_response() {
+        let data = b"* BAD not pop3\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_multi_response(&mut slice, MultilineOptions::default());
+
+        assert_eq!(actual.err().unwrap(), "unexpected response: * BAD not pop3\r\n".to_string());
+    }
+
+    #[test]
+    fn test_read_multi_response_strips_byte_order_mark() {
+        let data = b"+OK\r\n\xEF\xBB\xBFSubject: test\r\n\r\nHello\r\n.\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let actual = read_multi_response(&mut slice, MultilineOptions::default()).unwrap();
+
+        assert_eq!(actual, "Subject: test\r\n\r\nHello\r\n".to_string());
+    }
+
+    #[test]
+    fn test_read_multi_response_into_strips_byte_order_mark() {
+        let data = b"+OK\r\n\xEF\xBB\xBFSubject: test\r\n.\r\n";
+        let mut buffer = Vec::new();
+
+        read_multi_response_into(&mut data.as_ref(), &mut buffer, MultilineOptions::default()).unwrap();
+
+        assert_eq!(buffer, b"Subject: test\r\n");
+    }
+
+    #[test]
+    fn test_read_greeting_one_byte_at_a_time() {
+        let data = b"+OK  server ready\r\n+OK 2 320\r\n".to_vec();
+        let chunked = ChunkedReader { data, position: 0, chunk_size: 1 };
+        let mut reader = std::io::BufReader::with_capacity(1, chunked);
+
+        let greeting = read_greeting(&mut reader).unwrap();
+        let next = read_response(&mut reader).unwrap();
+
+        assert_eq!(greeting, "server ready".to_string());
+        assert_eq!(next, "2 320".to_string());
+    }
+
+    fn single_line_response(length: usize) -> Vec<u8> {
+        let mut response = b"+OK ".to_vec();
+        response.resize(length - 2, b'a');
+        response.extend_from_slice(b"\r\n");
+        response
+    }
+
+    fn multi_line_response(length: usize) -> Vec<u8> {
+        let mut response = b"+OK\r\n".to_vec();
+        response.resize(length - 5, b'a');
+        response.extend_from_slice(b"\r\n.\r\n");
+        response
+    }
+
+    fn reader_with_buffer_of(data: Vec<u8>, capacity: usize) -> std::io::BufReader<ChunkedReader> {
+        let mut data = data;
+        data.extend_from_slice(b"+OK next\r\n");
+        std::io::BufReader::with_capacity(capacity, ChunkedReader { data, position: 0, chunk_size: capacity })
+    }
+
+    #[test]
+    fn test_read_response_exactly_fills_the_buffer() {
+        for length in [512, 513] {
+            let mut reader = reader_with_buffer_of(single_line_response(length), 512);
+
+            let actual = read_response(&mut reader).unwrap();
+            let next = read_response(&mut reader).unwrap();
+
+            assert_eq!(actual.len(), length - 6, "response of {length} bytes");
+            assert_eq!(next, "next".to_string(), "response of {length} bytes");
+        }
+    }
+
+    #[test]
+    fn test_read_multi_response_exactly_fills_the_buffer() {
+        // 2048 ends the terminator at the end of the buffer, the others split it over two reads
+        for length in [2046, 2047, 2048, 2049] {
+            let mut reader = reader_with_buffer_of(multi_line_response(length), 2048);
+
+            let actual = read_multi_response(&mut reader, MultilineOptions::default()).unwrap();
+            let next = read_response(&mut reader).unwrap();
+
+            assert_eq!(actual.len(), length - 8, "response of {length} bytes");
+            assert!(actual.trim_end().bytes().all(|b| b == b'a'), "response of {length} bytes");
+            assert_eq!(next, "next".to_string(), "response of {length} bytes");
+        }
+    }
+
+    const LENIENT: MultilineOptions = MultilineOptions { lenient_terminator: true, max_lines: None };
+
+    #[test]
+    fn test_read_multi_response_lenient_terminator_at_connection_close() {
+        let data = b"+OK\r\nSubject: test\r\n\r\nHello.\r\n";
+
+        let lenient = read_multi_response(&mut data.as_ref(), LENIENT).unwrap();
+        let strict = read_multi_response(&mut data.as_ref(), MultilineOptions::default());
+
+        assert_eq!(lenient, "Subject: test\r\n\r\nHello".to_string());
+        assert_eq!(strict.err().unwrap(), "connection closed before the end of the response".to_string());
+    }
+
+    struct TimingOutReader {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl std::io::Read for TimingOutReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.position == self.data.len() {
+                return Err(std::io::Error::new(ErrorKind::WouldBlock, "timed out"));
+            }
+            let end = self.data.len().min(self.position + buf.len());
+            buf[..end - self.position].copy_from_slice(&self.data[self.position..end]);
+            let read = end - self.position;
+            self.position = end;
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn test_read_multi_response_lenient_terminator_at_timeout() {
+        let timing_out = |data: &[u8]| std::io::BufReader::new(TimingOutReader { data: data.to_vec(), position: 0 });
+
+        let cut_off = read_multi_response(&mut timing_out(b"+OK\r\nHello\r\n.\r"), LENIENT).unwrap();
+        let no_period = read_multi_response(&mut timing_out(b"+OK\r\nHello\r\n"), LENIENT);
+        let no_content = read_multi_response(&mut timing_out(b"+OK\r\n"), LENIENT);
+
+        assert_eq!(cut_off, "Hello\r\n".to_string());
+        assert_eq!(no_period.err().unwrap(), "could not read response: timed out".to_string());
+        assert_eq!(no_content.err().unwrap(), "could not read response: timed out".to_string());
+    }
+
+    #[test]
+    fn test_read_multi_response_timeout_after_sentence_is_not_a_terminator() {
+        let mut slow_server = std::io::BufReader::new(TimingOutReader { data: b"+OK\r\nThis is the end of sentence.\r\n".to_vec(), position: 0 });
+
+        let actual = read_multi_response(&mut slow_server, LENIENT);
+
+        assert_eq!(actual.err().unwrap(), "could not read response: timed out".to_string());
+    }
+
+    #[test]
+    fn test_read_multi_response_max_lines() {
+        let options = MultilineOptions { max_lines: Some(2), ..MultilineOptions::default() };
+
+        let at_max = read_multi_response(&mut b"+OK\r\none\r\ntwo\r\n.\r\n".as_ref(), options).unwrap();
+        let over_max = read_multi_response_with_partial(&mut b"+OK\r\none\r\ntwo\r\nthree\r\nfour\r\n.\r\n".as_ref(), options).err().unwrap();
+
+        assert_eq!(at_max, "one\r\ntwo\r\n".to_string());
+        assert_eq!(over_max.message, "response has more than the maximum of 2 lines".to_string());
+        assert_eq!(over_max.partial, b"+OK\r\none\r\ntwo\r\nthree\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_read_response_ok_text_split_over_reads() {
+        // every chunk size splits the line somewhere else: inside `+OK`, right after it, or mid-word
+        for chunk_size in 1..=8 {
+            let data = b"+OK message 3 deleted\r\n-ERR no such message\r\n".to_vec();
+            let chunked = ChunkedReader { data, position: 0, chunk_size };
+            let mut reader = std::io::BufReader::with_capacity(chunk_size, chunked);
+
+            let ok = read_response(&mut reader);
+            let err = read_response(&mut reader);
+
+            assert_eq!(ok, Ok("message 3 deleted".to_string()), "chunks of {chunk_size} bytes");
+            assert_eq!(err, Err(ResponseError::Server("no such message".to_string())), "chunks of {chunk_size} bytes");
+        }
+    }
+
+    #[test]
+    fn test_read_response_keeps_status_indicators_in_the_text() {
+        let data = b"+OK renamed +OK folder\r\n-ERR -ERR twice\r\n";
+        let mut slice: &[u8] = data.as_ref();
+
+        let ok = read_response(&mut slice).unwrap();
+        let err = read_response(&mut slice).unwrap_err();
+
+        assert_eq!(ok, "renamed +OK folder".to_string());
+        assert_eq!(err, ResponseError::Server("-ERR twice".to_string()));
+    }
 }
\ No newline at end of file
@@ -1,21 +1,34 @@
-use crate::errors::{ListError, StatError, UIDLError};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::errors::{CapaError, ListError, ListErrorKind, StatError, UIDLError};
+use crate::reader::translate_string_response;
 
 /// StatResponse is the number of messages and total size
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StatResponse {
-    pub number_of_message: i32,
-    pub total_size: i32,
+    pub number_of_message: u32,
+    pub total_size: u64,
+}
+
+impl StatResponse {
+    /// The average size of a message in bytes (rounded down), or None for an empty mailbox
+    pub fn average_size(&self) -> Option<u64> {
+        self.total_size.checked_div(u64::from(self.number_of_message))
+    }
 }
 
 impl TryFrom<String> for StatResponse {
     type Error = StatError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let pieces: Vec<_> = value.split(" ").collect();
+        // servers are allowed to add extra information after the count and size, so we ignore anything after those
+        let mut pieces = value.split_whitespace();
 
-        if pieces.len() == 2 {
-            let number_of_message = pieces[0].parse()?;
-            let total_size = pieces[1].parse()?;
+        if let (Some(number_of_message), Some(total_size)) = (pieces.next(), pieces.next()) {
+            let number_of_message = number_of_message.parse()?;
+            let total_size = total_size.parse()?;
 
             Ok(StatResponse {
                 number_of_message,
@@ -24,6 +37,7 @@ impl TryFrom<String> for StatResponse {
         } else {
             Err(Self::Error {
                 message: format!("invalid stat response: {}", value),
+                code: None,
             })
         }
     }
@@ -39,10 +53,11 @@ impl TryFrom<String> for ListResponse {
     type Error = ListError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let messages = value.split('\n')
-            .map(|v| v.replace('\r', ""))
-            .filter(|v| !v.is_empty() && v != ".") // list probably ends with a single dot
-            .map(|v| v.try_into())
+        // items are parsed from the borrowed line, so a large mailbox does not need an extra allocation per message
+        let messages = value.lines()
+            .map(|v| v.trim_end_matches('\r'))
+            .filter(|v| !v.is_empty())
+            .map(ItemResponse::try_from)
             .collect::<Result<Vec<ItemResponse>, ListError>>()?;
 
         Ok(ListResponse {
@@ -51,42 +66,168 @@ impl TryFrom<String> for ListResponse {
     }
 }
 
+/// Parse an entire LIST response as sent by the server, including the status line and the terminating period
+impl FromStr for ListResponse {
+    type Err = ListError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (status, items) = raw.split_once('\n').unwrap_or((raw, ""));
+        translate_string_response(status.to_string())?;
+        // unlike the reader, a full response still ends with the terminating period
+        let items = match items.trim_end() {
+            "." => "",
+            items => items.strip_suffix("\n.").unwrap_or(items),
+        };
+        items.to_string().try_into()
+    }
+}
+
 /// ItemResponse is the id and size of a message
 #[derive(Debug)]
 pub struct ItemResponse {
-    pub message_id: i32,
-    pub size: i32,
+    pub message_id: u32,
+    pub size: u64,
 }
 
 impl TryFrom<String> for ItemResponse {
     type Error = ListError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let pieces: Vec<_> = value.split(" ").collect();
+        value.as_str().try_into()
+    }
+}
 
-        if pieces.len() == 2 {
-            let message_id = pieces[0].parse()?;
-            let size = pieces[1].parse()?;
+impl TryFrom<&str> for ItemResponse {
+    type Error = ListError;
 
-            Ok(ItemResponse {
-                message_id,
-                size,
-            })
-        } else {
-            Err(ListError {
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.split_once(' ') {
+            Some((message_id, size)) if !size.contains(' ') => Ok(ItemResponse {
+                message_id: message_id.parse()?,
+                size: size.parse()?,
+            }),
+            _ => Err(ListError {
                 message: format!("invalid list item: {}", value),
-            })
+                code: None,
+                kind: ListErrorKind::Other,
+            }),
         }
     }
 }
 
-/// RetrieveResponse is the content of a message and its id
+/// RetrieveResponse is the content of a message and its id.
+/// The data is the message as the server sent it, without dot-stuffing, up to and including the line ending before the terminating period.
+/// It does not contain that period, use `data_with_terminator` when you need it
 #[derive(Debug)]
 pub struct RetrieveResponse {
-    pub message_id: i32,
+    pub message_id: u32,
     pub data: String,
 }
 
+impl RetrieveResponse {
+    /// The content of the message with every line ending normalized to CRLF, as required by RFC 5322
+    pub fn data_with_crlf(&self) -> String {
+        to_crlf(&self.data)
+    }
+
+    /// The content of the message followed by the terminating period, the way the server ends a multiline response
+    pub fn data_with_terminator(&self) -> String {
+        if self.data.is_empty() || self.data.ends_with('\n') {
+            format!("{}.", self.data)
+        } else {
+            format!("{}\r\n.", self.data)
+        }
+    }
+
+    /// The content of the message with every line ending normalized to LF
+    pub fn data_lf(&self) -> String {
+        to_lf(&self.data)
+    }
+
+    /// Split the message into its headers and its body, at the first blank line.
+    /// A message without a blank line is all headers, so the body is empty
+    pub fn split_headers_body(&self) -> (&str, &str) {
+        let crlf = self.data.find("\r\n\r\n").map(|i| (i, 4));
+        let lf = self.data.find("\n\n").map(|i| (i, 2));
+        let separator = match (crlf, lf) {
+            (Some(crlf), Some(lf)) => Some(if crlf.0 < lf.0 { crlf } else { lf }),
+            (crlf, lf) => crlf.or(lf),
+        };
+
+        match separator {
+            Some((index, length)) => (&self.data[..index], &self.data[index + length..]),
+            None => (&self.data, ""),
+        }
+    }
+
+    /// The lines of the message without their (CRLF or LF) line endings
+    pub fn lines(&self) -> impl Iterator<Item = &str> + '_ {
+        self.data.lines()
+    }
+
+    /// The value of the first header with the given (case-insensitive) name, with folded lines joined by a space
+    pub fn header(&self, name: &str) -> Option<String> {
+        let (headers, _) = self.split_headers_body();
+        let mut lines = headers.lines().skip_while(|line| !is_header(line, name));
+        let first = lines.next()?.split_once(':')?.1.trim().to_string();
+
+        Some(lines.take_while(|line| line.starts_with([' ', '\t']))
+            .fold(first, |value, line| format!("{value} {}", line.trim())))
+    }
+
+    /// The `Date` header of the message, parsed according to RFC 5322 (including its obsolete syntax)
+    #[cfg(feature = "time")]
+    pub fn date(&self) -> Option<time::OffsetDateTime> {
+        self.header("Date").and_then(|date| crate::date::parse_rfc5322_date(&date))
+    }
+
+    /// A reader over the content of the message, for passing it to anything that expects `impl Read`
+    pub fn reader(&self) -> impl Read + '_ {
+        self.data.as_bytes()
+    }
+}
+
+fn is_header(line: &str, name: &str) -> bool {
+    line.split_once(':').is_some_and(|(key, _)| key.trim_end().eq_ignore_ascii_case(name))
+}
+
+impl AsRef<[u8]> for RetrieveResponse {
+    fn as_ref(&self) -> &[u8] {
+        self.data.as_bytes()
+    }
+}
+
+/// The line endings to use when writing out a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    /// Keep the line endings as they were received (normally CRLF, as required by the RFC)
+    #[default]
+    Preserve,
+    /// CRLF, as expected by the RFCs and most mail tools
+    Crlf,
+    /// LF, as expected by some Unix tools
+    Lf,
+}
+
+impl LineEnding {
+    pub(crate) fn apply(&self, data: &str) -> String {
+        match self {
+            LineEnding::Preserve => data.to_string(),
+            LineEnding::Crlf => to_crlf(data),
+            LineEnding::Lf => to_lf(data),
+        }
+    }
+}
+
+pub(crate) fn to_lf(data: &str) -> String {
+    data.replace("\r\n", "\n")
+}
+
+pub(crate) fn to_crlf(data: &str) -> String {
+    // first go to LF, so existing CRLF endings don't become CR CR LF
+    to_lf(data).replace('\n', "\r\n")
+}
+
 /// UIDLResponse is a list of messages with their message id and unique id
 #[derive(Debug)]
 pub struct UIDLResponse {
@@ -97,10 +238,10 @@ impl TryFrom<String> for UIDLResponse {
     type Error = UIDLError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let messages = value.split('\n')
-            .map(|v| v.replace('\r', ""))
-            .filter(|v| !v.is_empty() && v != ".") // UIDL probably ends with a single dot
-            .map(|v| v.try_into())
+        let messages = value.lines()
+            .map(|v| v.trim_end_matches('\r'))
+            .filter(|v| !v.is_empty())
+            .map(UIDLItem::try_from)
             .collect::<Result<Vec<UIDLItem>, UIDLError>>()?;
 
         Ok(UIDLResponse {
@@ -112,7 +253,7 @@ impl TryFrom<String> for UIDLResponse {
 /// UIDLItem is the id and unique id of a message
 #[derive(Debug)]
 pub struct UIDLItem {
-    pub message_id: i32,
+    pub message_id: u32,
     pub unique_id: String,
 }
 
@@ -120,11 +261,22 @@ impl TryFrom<String> for UIDLItem {
     type Error = UIDLError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let pieces: Vec<_> = value.split(" ").collect();
+        value.as_str().try_into()
+    }
+}
+
+impl TryFrom<&str> for UIDLItem {
+    type Error = UIDLError;
 
-        if pieces.len() == 2 {
-            let message_id = pieces[0].parse()?;
-            let unique_id = pieces[1].to_string();
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        // some servers pad the fields, so split on any amount of whitespace. Unique ids can contain any printable
+        // character except space (RFC 1939), so we take the entire second field as is
+        let mut pieces = value.split_whitespace();
+
+        if let (Some(message_id), Some(unique_id)) = (pieces.next(), pieces.next()) {
+            let message_id = message_id.parse()?;
+            // unlike a LIST item, this is one allocation per message, as the item owns its unique id
+            let unique_id = unique_id.to_string();
 
             Ok(UIDLItem {
                 message_id,
@@ -133,19 +285,106 @@ impl TryFrom<String> for UIDLItem {
         } else {
             Err(UIDLError {
                 message: format!("invalid UIDL item: {}", value),
+                code: None,
             })
         }
     }
 }
 
+/// MessageOverview is the size and unique id of a message, from LIST and UIDL.
+/// A field is None when the message was missing from that response
+#[derive(Debug, PartialEq, Eq)]
+pub struct MessageOverview {
+    pub message_id: u32,
+    pub size: Option<u64>,
+    pub unique_id: Option<String>,
+}
+
+impl MessageOverview {
+    /// Join the LIST and UIDL responses by message id, ordered by message id
+    pub(crate) fn join(list: ListResponse, uidl: UIDLResponse) -> Vec<MessageOverview> {
+        let mut overviews: BTreeMap<u32, MessageOverview> = BTreeMap::new();
+        for item in list.messages {
+            overviews.entry(item.message_id)
+                .or_insert_with(|| MessageOverview::empty(item.message_id))
+                .size = Some(item.size);
+        }
+        for item in uidl.messages {
+            overviews.entry(item.message_id)
+                .or_insert_with(|| MessageOverview::empty(item.message_id))
+                .unique_id = Some(item.unique_id);
+        }
+        overviews.into_values().collect()
+    }
+
+    fn empty(message_id: u32) -> MessageOverview {
+        MessageOverview {
+            message_id,
+            size: None,
+            unique_id: None,
+        }
+    }
+}
+
 /// TopResponse is the id of the message, the number of lines that top had to return, and the data of those lines
 #[derive(Debug)]
 pub struct TopResponse {
-    pub message_id: i32,
-    pub number_of_lines: i32,
+    pub message_id: u32,
+    pub number_of_lines: u32,
     pub data: String,
 }
 
+/// CapaResponse is the list of capabilities the server advertises
+#[derive(Debug, Clone)]
+pub struct CapaResponse {
+    pub capabilities: Vec<Capability>,
+}
+
+impl CapaResponse {
+    /// Check whether the server advertised the given capability (e.g. `TOP` or `UIDL`)
+    pub fn supports(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// The arguments of the given capability, e.g. the mechanisms of `SASL`
+    pub fn arguments(&self, name: &str) -> Option<&[String]> {
+        self.get(name).map(|c| c.arguments.as_slice())
+    }
+
+    fn get(&self, name: &str) -> Option<&Capability> {
+        self.capabilities.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl TryFrom<String> for CapaResponse {
+    type Error = CapaError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let capabilities = value.split('\n')
+            .map(|v| v.replace('\r', ""))
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                let mut pieces = v.split_whitespace().map(|p| p.to_string());
+                Capability {
+                    name: pieces.next().unwrap_or_default(),
+                    arguments: pieces.collect(),
+                }
+            })
+            .collect();
+
+        Ok(CapaResponse {
+            capabilities,
+        })
+    }
+}
+
+/// Capability is the name of a capability and its (optional) arguments
+#[derive(Debug, Clone)]
+pub struct Capability {
+    pub name: String,
+    pub arguments: Vec<String>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,6 +397,21 @@ mod tests {
         assert_eq!(actual.total_size, 12345);
     }
 
+    #[test]
+    fn test_stat_response_try_from_ignores_extra_info() {
+        let actual: StatResponse = "2 12345 extra info".to_string().try_into().unwrap();
+
+        assert_eq!(actual.number_of_message, 2);
+        assert_eq!(actual.total_size, 12345);
+    }
+
+    #[test]
+    fn test_stat_response_try_from_fails_for_single_number() {
+        let actual: Result<StatResponse, StatError> = "2".to_string().try_into();
+
+        assert!(actual.is_err())
+    }
+
     #[test]
     fn test_item_response_try_from() {
         let actual: ItemResponse = "2 12345".to_string().try_into().unwrap();
@@ -192,8 +446,8 @@ mod tests {
     }
 
     #[test]
-    fn test_list_response_try_from_ending_period() {
-        let actual: ListResponse = "1 12345\n2 2345\n.".to_string().try_into().unwrap();
+    fn test_list_response_try_from_ending_newline() {
+        let actual: ListResponse = "1 12345\n2 2345\n".to_string().try_into().unwrap();
 
         assert_eq!(actual.messages.len(), 2);
         assert_eq!(actual.messages[0].message_id, 1);
@@ -252,4 +506,214 @@ mod tests {
         assert_eq!(actual.messages[1].message_id, 2);
         assert_eq!(actual.messages[1].unique_id, "QhdPYR:00WBw1Ph7x7".to_string());
     }
+
+    #[test]
+    fn test_retrieve_response_data_with_crlf_normalizes_mixed_endings() {
+        let response = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\r\nFrom: me\n\nBody\r\n".to_string(),
+        };
+
+        assert_eq!(response.data_with_crlf(), "Subject: test\r\nFrom: me\r\n\r\nBody\r\n".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_response_data_lf_normalizes_mixed_endings() {
+        let response = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\r\nFrom: me\n\nBody\r\n".to_string(),
+        };
+
+        assert_eq!(response.data_lf(), "Subject: test\nFrom: me\n\nBody\n".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_response_reader_reads_data() {
+        let response = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\r\n\r\nBody".to_string(),
+        };
+        let mut actual = String::new();
+
+        response.reader().read_to_string(&mut actual).unwrap();
+
+        assert_eq!(actual, response.data);
+        assert_eq!(response.as_ref(), response.data.as_bytes());
+    }
+
+    #[test]
+    fn test_capa_response_try_from() {
+        let actual: CapaResponse = "TOP\r\nUIDL\r\nSASL PLAIN CRAM-MD5".to_string().try_into().unwrap();
+
+        assert_eq!(actual.capabilities.len(), 3);
+        assert!(actual.supports("top"));
+        assert!(!actual.supports("STLS"));
+        assert_eq!(actual.arguments("SASL").unwrap(), &["PLAIN".to_string(), "CRAM-MD5".to_string()]);
+    }
+
+    #[test]
+    fn test_retrieve_response_split_headers_body() {
+        let response = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\r\nFrom: me\r\n\r\nBody\r\n\r\nMore body".to_string(),
+        };
+
+        let (headers, body) = response.split_headers_body();
+
+        assert_eq!(headers, "Subject: test\r\nFrom: me");
+        assert_eq!(body, "Body\r\n\r\nMore body");
+    }
+
+    #[test]
+    fn test_retrieve_response_split_headers_body_with_lf() {
+        let response = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\n\nBody".to_string(),
+        };
+
+        assert_eq!(response.split_headers_body(), ("Subject: test", "Body"));
+    }
+
+    #[test]
+    fn test_retrieve_response_split_headers_body_without_body() {
+        let response = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\r\nFrom: me".to_string(),
+        };
+
+        assert_eq!(response.split_headers_body(), ("Subject: test\r\nFrom: me", ""));
+    }
+
+    #[test]
+    fn test_list_response_from_str_full_response() {
+        let actual: ListResponse = "+OK 2 messages (300 octets)\r\n1 100\r\n2 200\r\n.\r\n".parse().unwrap();
+
+        assert_eq!(actual.messages.len(), 2);
+        assert_eq!(actual.messages[0].message_id, 1);
+        assert_eq!(actual.messages[0].size, 100);
+        assert_eq!(actual.messages[1].message_id, 2);
+        assert_eq!(actual.messages[1].size, 200);
+    }
+
+    #[test]
+    fn test_list_response_from_str_err_response() {
+        let actual: Result<ListResponse, ListError> = "-ERR no such message\r\n".parse();
+
+        assert_eq!(actual.err().unwrap().message, "no such message".to_string());
+    }
+
+    #[test]
+    fn test_uidl_item_try_from_with_padding() {
+        let actual: UIDLItem = "  1  whqtswO00WBw418f9t5JxYwZ ".to_string().try_into().unwrap();
+
+        assert_eq!(actual.message_id, 1);
+        assert_eq!(actual.unique_id, "whqtswO00WBw418f9t5JxYwZ".to_string());
+    }
+
+    #[test]
+    fn test_uidl_item_try_from_with_unusual_characters() {
+        let actual: UIDLItem = "2 QhdPYR:00WBw1Ph7x7!~{}".to_string().try_into().unwrap();
+
+        assert_eq!(actual.unique_id, "QhdPYR:00WBw1Ph7x7!~{}".to_string());
+    }
+
+    #[test]
+    fn test_uidl_item_try_from_fails_without_unique_id() {
+        let actual: Result<UIDLItem, UIDLError> = "2".to_string().try_into();
+
+        assert!(actual.is_err());
+    }
+
+    #[test]
+    fn test_line_ending_apply() {
+        let data = "Subject: test\r\n\nBody";
+
+        assert_eq!(LineEnding::Preserve.apply(data), data.to_string());
+        assert_eq!(LineEnding::Crlf.apply(data), "Subject: test\r\n\r\nBody".to_string());
+        assert_eq!(LineEnding::Lf.apply(data), "Subject: test\n\nBody".to_string());
+    }
+
+    #[test]
+    fn test_retrieve_response_header_unfolds_lines() {
+        let response = RetrieveResponse {
+            message_id: 1,
+            data: "subject: a long\r\n  subject\r\nDate: Tue, 1 Jul 2003 10:52:37 +0200\r\n\r\nDate: not a header".to_string(),
+        };
+
+        assert_eq!(response.header("Subject"), Some("a long subject".to_string()));
+        assert_eq!(response.header("date"), Some("Tue, 1 Jul 2003 10:52:37 +0200".to_string()));
+        assert_eq!(response.header("From"), None);
+    }
+
+    #[test]
+    fn test_negative_numbers_are_rejected() {
+        let item: Result<ItemResponse, ListError> = "-1 100".to_string().try_into();
+        let stat: Result<StatResponse, StatError> = "-1 100".to_string().try_into();
+        let size: Result<ItemResponse, ListError> = "1 -100".to_string().try_into();
+        let uidl: Result<UIDLItem, UIDLError> = "-1 whqtswO00WBw418f9t5JxYwZ".to_string().try_into();
+
+        assert_eq!(item.unwrap_err().message, "could not parse list response numbers: invalid digit found in string".to_string());
+        assert!(stat.is_err());
+        assert!(size.is_err());
+        assert!(uidl.is_err());
+    }
+
+    #[test]
+    fn test_retrieve_response_lines_keeps_leading_periods() {
+        let response = RetrieveResponse {
+            message_id: 1,
+            data: "Subject: test\r\n\r\n.hidden\nline\r\n..".to_string(),
+        };
+
+        let actual: Vec<&str> = response.lines().collect();
+
+        assert_eq!(actual, vec!["Subject: test", "", ".hidden", "line", ".."]);
+    }
+
+    #[test]
+    fn test_message_overview_join_with_missing_messages() {
+        let list: ListResponse = "1 120\r\n2 200".to_string().try_into().unwrap();
+        let uidl: UIDLResponse = "2 QhdPYR:00WBw1Ph7x7\r\n3 whqtswO00WBw418f9t5JxYwZ".to_string().try_into().unwrap();
+
+        let actual = MessageOverview::join(list, uidl);
+
+        assert_eq!(actual, vec![
+            MessageOverview { message_id: 1, size: Some(120), unique_id: None },
+            MessageOverview { message_id: 2, size: Some(200), unique_id: Some("QhdPYR:00WBw1Ph7x7".to_string()) },
+            MessageOverview { message_id: 3, size: None, unique_id: Some("whqtswO00WBw418f9t5JxYwZ".to_string()) },
+        ]);
+    }
+
+    #[test]
+    fn test_item_response_with_extra_field_is_invalid() {
+        let actual = ItemResponse::try_from("1 120 extra");
+
+        assert_eq!(actual.unwrap_err().message, "invalid list item: 1 120 extra".to_string());
+    }
+
+    #[test]
+    fn test_uidl_item_try_from_borrowed_line() {
+        let line = "2 QhdPYR:00WBw1Ph7x7";
+
+        let actual = UIDLItem::try_from(line).unwrap();
+
+        assert_eq!(actual.message_id, 2);
+        assert_eq!(actual.unique_id, "QhdPYR:00WBw1Ph7x7".to_string());
+    }
+
+    #[test]
+    fn test_stat_response_average_size() {
+        let stat = StatResponse { number_of_message: 3, total_size: 1000 };
+        let empty = StatResponse { number_of_message: 0, total_size: 0 };
+
+        assert_eq!(stat.average_size(), Some(333));
+        assert_eq!(empty.average_size(), None);
+    }
+
+    #[test]
+    fn test_list_response_from_str_empty_mailbox() {
+        let actual: ListResponse = "+OK 0 messages\r\n.\r\n".parse().unwrap();
+
+        assert!(actual.messages.is_empty());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::errors::{CapaError, DeleteError, ListError, NoopError, Pop3Error, ResetError, RetrieveError, StatError, TopError, UIDLError};
+use crate::responses::{CapaResponse, ItemResponse, ListResponse, MessageOverview, RetrieveResponse, StatResponse, TopResponse, UIDLItem, UIDLResponse};
+use crate::Pop3Client;
+
+/// The SharedPop3Client lets multiple threads use the same Pop3Client. Every command locks the client,
+/// runs, and unlocks it again, so commands of different threads never interleave.
+/// Cloning a SharedPop3Client gives you another handle to the same client
+#[derive(Clone)]
+pub struct SharedPop3Client {
+    client: Arc<Mutex<Pop3Client>>,
+}
+
+impl From<Pop3Client> for SharedPop3Client {
+    fn from(client: Pop3Client) -> Self {
+        SharedPop3Client {
+            client: Arc::new(Mutex::new(client)),
+        }
+    }
+}
+
+impl SharedPop3Client {
+    /// Create a new SharedPop3Client for the given client
+    pub fn new(client: Pop3Client) -> SharedPop3Client {
+        client.into()
+    }
+
+    /// Run multiple commands while holding the lock, or call a method that this wrapper does not offer
+    pub fn with<R, E: From<String>>(&self, action: impl FnOnce(&mut Pop3Client) -> Result<R, E>) -> Result<R, E> {
+        action(&mut *self.lock()?)
+    }
+
+    /// See `Pop3Client::stat`
+    pub fn stat(&self) -> Result<StatResponse, StatError> {
+        self.lock()?.stat()
+    }
+
+    /// See `Pop3Client::list`
+    pub fn list(&self) -> Result<ListResponse, ListError> {
+        self.lock()?.list()
+    }
+
+    /// See `Pop3Client::list_id`
+    pub fn list_id(&self, message_id: u32) -> Result<ItemResponse, ListError> {
+        self.lock()?.list_id(message_id)
+    }
+
+    /// See `Pop3Client::message_numbers`
+    pub fn message_numbers(&self) -> Result<Vec<u32>, ListError> {
+        self.lock()?.message_numbers()
+    }
+
+    /// See `Pop3Client::list_last`
+    pub fn list_last(&self, number_of_messages: u32) -> Result<ListResponse, ListError> {
+        self.lock()?.list_last(number_of_messages)
+    }
+
+    /// See `Pop3Client::list_including_deleted`
+    pub fn list_including_deleted(&self) -> Result<Vec<(ItemResponse, bool)>, ListError> {
+        self.lock()?.list_including_deleted()
+    }
+
+    /// See `Pop3Client::list_larger_than`
+    pub fn list_larger_than(&self, bytes: u64) -> Result<ListResponse, ListError> {
+        self.lock()?.list_larger_than(bytes)
+    }
+
+    /// See `Pop3Client::list_smaller_than`
+    pub fn list_smaller_than(&self, bytes: u64) -> Result<ListResponse, ListError> {
+        self.lock()?.list_smaller_than(bytes)
+    }
+
+    /// See `Pop3Client::retrieve_as_string`
+    pub fn retrieve_as_string(&self, message_id: u32) -> Result<RetrieveResponse, RetrieveError> {
+        self.lock()?.retrieve_as_string(message_id)
+    }
+
+    /// See `Pop3Client::drain`. The client stays locked until every message is drained
+    pub fn drain<E: From<Pop3Error>>(&self, handle: impl FnMut(&RetrieveResponse) -> Result<(), E>) -> Result<u32, E> {
+        self.lock().map_err(|err| Pop3Error::from(ListError::from(err)))?.drain(handle)
+    }
+
+    /// See `Pop3Client::retrieve_into`
+    pub fn retrieve_into(&self, message_id: u32, buffer: &mut Vec<u8>) -> Result<(), RetrieveError> {
+        self.lock()?.retrieve_into(message_id, buffer)
+    }
+
+    /// See `Pop3Client::retrieve_with_hash`
+    pub fn retrieve_with_hash(&self, message_id: u32) -> Result<(RetrieveResponse, [u8; 32]), RetrieveError> {
+        self.lock()?.retrieve_with_hash(message_id)
+    }
+
+    /// See `Pop3Client::retrieve_body`
+    pub fn retrieve_body(&self, message_id: u32) -> Result<String, RetrieveError> {
+        self.lock()?.retrieve_body(message_id)
+    }
+
+    /// See `Pop3Client::retrieve_last_as_string`
+    pub fn retrieve_last_as_string(&self) -> Result<RetrieveResponse, RetrieveError> {
+        self.lock()?.retrieve_last_as_string()
+    }
+
+    /// See `Pop3Client::retrieve`
+    pub fn retrieve(&self, message_id: u32, writer: &mut impl Write) -> Result<usize, RetrieveError> {
+        self.lock()?.retrieve(message_id, writer)
+    }
+
+    /// See `Pop3Client::retrieve_if_under`
+    pub fn retrieve_if_under(&self, message_id: u32, max_bytes: u64, writer: &mut impl Write) -> Result<bool, RetrieveError> {
+        self.lock()?.retrieve_if_under(message_id, max_bytes, writer)
+    }
+
+    /// See `Pop3Client::retrieve_last`
+    pub fn retrieve_last(&self, writer: &mut impl Write) -> Result<usize, RetrieveError> {
+        self.lock()?.retrieve_last(writer)
+    }
+
+    /// See `Pop3Client::reset`
+    pub fn reset(&self) -> Result<(), ResetError> {
+        self.lock()?.reset()
+    }
+
+    /// See `Pop3Client::reset_verbose`
+    pub fn reset_verbose(&self) -> Result<String, ResetError> {
+        self.lock()?.reset_verbose()
+    }
+
+    /// See `Pop3Client::delete`
+    pub fn delete(&self, message_id: u32) -> Result<(), DeleteError> {
+        self.lock()?.delete(message_id)
+    }
+
+    /// See `Pop3Client::delete_verbose`
+    pub fn delete_verbose(&self, message_id: u32) -> Result<String, DeleteError> {
+        self.lock()?.delete_verbose(message_id)
+    }
+
+    /// See `Pop3Client::delete_many`
+    pub fn delete_many(&self, message_ids: &[u32]) -> Result<u32, DeleteError> {
+        self.lock()?.delete_many(message_ids)
+    }
+
+    /// See `Pop3Client::delete_range`
+    pub fn delete_range(&self, start: u32, end: u32) -> Result<u32, DeleteError> {
+        self.lock()?.delete_range(start, end)
+    }
+
+    /// See `Pop3Client::delete_all`
+    pub fn delete_all(&self) -> Result<u32, DeleteError> {
+        self.lock()?.delete_all()
+    }
+
+    /// See `Pop3Client::noop`
+    pub fn noop(&self) -> Result<(), NoopError> {
+        self.lock()?.noop()
+    }
+
+    /// See `Pop3Client::noop_verbose`
+    pub fn noop_verbose(&self) -> Result<String, NoopError> {
+        self.lock()?.noop_verbose()
+    }
+
+    /// See `Pop3Client::uidl`
+    pub fn uidl(&self) -> Result<UIDLResponse, UIDLError> {
+        self.lock()?.uidl()
+    }
+
+    /// See `Pop3Client::uidl_with_id`
+    pub fn uidl_with_id(&self, message_id: u32) -> Result<UIDLItem, UIDLError> {
+        self.lock()?.uidl_with_id(message_id)
+    }
+
+    /// See `Pop3Client::new_since`
+    pub fn new_since(&self, seen: &HashSet<String>) -> Result<Vec<u32>, UIDLError> {
+        self.lock()?.new_since(seen)
+    }
+
+    /// See `Pop3Client::overview`
+    pub fn overview(&self) -> Result<Vec<MessageOverview>, Pop3Error> {
+        self.lock().map_err(ListError::from)?.overview()
+    }
+
+    /// See `Pop3Client::top`
+    pub fn top(&self, message_id: u32, number_of_lines: u32) -> Result<TopResponse, TopError> {
+        self.lock()?.top(message_id, number_of_lines)
+    }
+
+    /// See `Pop3Client::top_all`
+    pub fn top_all(&self, number_of_lines: u32) -> Result<Vec<TopResponse>, TopError> {
+        self.lock()?.top_all(number_of_lines)
+    }
+
+    /// See `Pop3Client::capa`
+    pub fn capa(&self) -> Result<CapaResponse, CapaError> {
+        self.lock()?.capa()
+    }
+
+    /// See `Pop3Client::capabilities`. Returns a copy, because the client is unlocked again afterwards
+    pub fn capabilities(&self) -> Result<CapaResponse, CapaError> {
+        self.lock()?.capabilities().cloned()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Pop3Client>, String> {
+        self.client.lock()
+            .map_err(|_| "another thread panicked while using the client".to_string())
+    }
+}
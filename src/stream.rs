@@ -0,0 +1,96 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use rustls::pki_types::CertificateDer;
+use rustls::{CipherSuite, ClientConfig, ClientConnection, ProtocolVersion, StreamOwned};
+
+use crate::errors::ConnectionError;
+
+/// How the connection to the server is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Security {
+    /// TLS from the start, normally on port 995
+    #[default]
+    ImplicitTls,
+    /// Connect without TLS, normally on port 110, and upgrade the connection with the STLS command (RFC 2595)
+    StartTls,
+    /// No TLS at all, so everything, including the password, is sent unencrypted. Only use this for local testing
+    Plaintext,
+}
+
+/// The stream the builder sets up, depending on the `Security` of the connection
+pub enum Pop3Stream {
+    /// TLS over TCP, either from the start or after STLS
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+    /// Plaintext TCP
+    Plain(TcpStream),
+}
+
+/// TlsInfo is what the TLS handshake negotiated, e.g. for debugging handshake issues or for audit logs
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    pub protocol_version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    /// The certificate chain of the server (DER encoded), starting with the certificate of the server itself
+    pub peer_certificates: Vec<CertificateDer<'static>>,
+}
+
+impl Pop3Stream {
+    /// What the TLS handshake negotiated, or None for a plaintext stream
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match self {
+            Pop3Stream::Tls(stream) => Some(TlsInfo {
+                protocol_version: stream.conn.protocol_version(),
+                cipher_suite: stream.conn.negotiated_cipher_suite().map(|suite| suite.suite()),
+                peer_certificates: stream.conn.peer_certificates()
+                    .map(|certificates| certificates.iter().map(|certificate| certificate.clone().into_owned()).collect())
+                    .unwrap_or_default(),
+            }),
+            Pop3Stream::Plain(_) => None,
+        }
+    }
+
+    pub(crate) fn tls(host: &str, config: Arc<ClientConfig>, tcp_stream: TcpStream) -> Result<Pop3Stream, ConnectionError> {
+        let server_name = host.to_string().try_into()?;
+        let connection = ClientConnection::new(config, server_name)?;
+        Ok(Pop3Stream::Tls(Box::new(StreamOwned::new(connection, tcp_stream))))
+    }
+
+    /// Close the TLS session (if any), returning the TCP stream
+    pub(crate) fn into_tcp_stream(self) -> Result<TcpStream, ConnectionError> {
+        match self {
+            Pop3Stream::Tls(mut stream) => {
+                stream.conn.send_close_notify();
+                stream.conn.complete_io(&mut stream.sock)?;
+                Ok(stream.sock)
+            }
+            Pop3Stream::Plain(stream) => Ok(stream),
+        }
+    }
+}
+
+impl Read for Pop3Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Pop3Stream::Tls(stream) => stream.read(buf),
+            Pop3Stream::Plain(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Pop3Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Pop3Stream::Tls(stream) => stream.write(buf),
+            Pop3Stream::Plain(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Pop3Stream::Tls(stream) => stream.flush(),
+            Pop3Stream::Plain(stream) => stream.flush(),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::io::{Read, Write};
+use std::ops::{Deref, DerefMut};
+
+use crate::stream::Pop3Stream;
+use crate::Pop3Client;
+
+/// Guard for deleting messages that only get deleted when you call `commit`.
+/// When the guard is dropped without committing, for example because of an early return with `?` or a panic,
+/// it sends RSET, so the server unmarks the messages instead of deleting them at QUIT.
+/// RSET unmarks every message of the session, which is why a transaction can only begin when none are marked yet.
+/// It derefs to the client, so you can run any command while the transaction is open
+pub struct DeletionTransaction<'a, S: Read + Write = Pop3Stream> {
+    client: &'a mut Pop3Client<S>,
+    committed: bool,
+}
+
+impl<'a, S: Read + Write> DeletionTransaction<'a, S> {
+    pub(crate) fn new(client: &'a mut Pop3Client<S>) -> DeletionTransaction<'a, S> {
+        DeletionTransaction {
+            client,
+            committed: false,
+        }
+    }
+
+    /// Keep the deletions, which the server executes when the session ends with QUIT
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl<S: Read + Write> Deref for DeletionTransaction<'_, S> {
+    type Target = Pop3Client<S>;
+
+    fn deref(&self) -> &Self::Target {
+        self.client
+    }
+}
+
+impl<S: Read + Write> DerefMut for DeletionTransaction<'_, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client
+    }
+}
+
+impl<S: Read + Write> Drop for DeletionTransaction<'_, S> {
+    fn drop(&mut self) {
+        if !self.committed {
+            // nothing we can do if this fails, so we ignore the result
+            let _ = self.client.reset();
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt::{Debug, Formatter};
+use std::io::{BufRead, Read};
+
+const REDACTED: &str = "***";
+
+/// An entry of the transcript, which is either a command sent to the server or a response received from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscriptEntry {
+    /// A command sent to the server (without the ending CRLF), with passwords replaced by `***`
+    Sent(String),
+    /// A raw response received from the server
+    Received(String),
+}
+
+impl TranscriptEntry {
+    pub(crate) fn sent(command: &str) -> TranscriptEntry {
+        TranscriptEntry::Sent(redact(command))
+    }
+
+    pub(crate) fn sent_secret() -> TranscriptEntry {
+        TranscriptEntry::Sent(REDACTED.to_string())
+    }
+
+    pub(crate) fn redacted(self, secret: &RedactedString) -> TranscriptEntry {
+        match self {
+            TranscriptEntry::Sent(command) => TranscriptEntry::Sent(secret.redact_in(&command)),
+            TranscriptEntry::Received(response) => TranscriptEntry::Received(secret.redact_in(&response)),
+        }
+    }
+}
+
+/// A secret, like a password, that is replaced by `***` in Debug output, errors and transcripts
+#[derive(Clone)]
+pub(crate) struct RedactedString(String);
+
+impl RedactedString {
+    pub(crate) fn new(secret: &str) -> RedactedString {
+        RedactedString(secret.to_string())
+    }
+
+    /// The actual secret, only meant for sending it to the server
+    pub(crate) fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Replace every occurrence of the secret in the given text, e.g. when a server echoes it back in an error
+    pub(crate) fn redact_in(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            text.to_string()
+        } else {
+            text.replace(&self.0, REDACTED)
+        }
+    }
+}
+
+impl Debug for RedactedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Replace the secret part of commands that contain a password (or something derived from one)
+fn redact(command: &str) -> String {
+    let pieces: Vec<_> = command.splitn(3, ' ').collect();
+
+    match pieces.as_slice() {
+        [name, _] if name.eq_ignore_ascii_case("PASS") => format!("{name} {REDACTED}"),
+        [name, user, _] if name.eq_ignore_ascii_case("APOP") => format!("{name} {user} {REDACTED}"),
+        [name, mechanism, _] if name.eq_ignore_ascii_case("AUTH") => format!("{name} {mechanism} {REDACTED}"),
+        _ => command.to_string(),
+    }
+}
+
+/// Reader that keeps a copy of everything that passes through it
+pub(crate) struct RecordingReader<'a, R: Read> {
+    inner: &'a mut R,
+    recorded: Vec<u8>,
+}
+
+impl<'a, R: Read> RecordingReader<'a, R> {
+    pub(crate) fn new(inner: &'a mut R) -> Self {
+        RecordingReader {
+            inner,
+            recorded: Vec::new(),
+        }
+    }
+
+    pub(crate) fn into_entry(self) -> TranscriptEntry {
+        TranscriptEntry::Received(String::from_utf8_lossy(&self.recorded).into_owned())
+    }
+}
+
+impl<R: Read> Read for RecordingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.recorded.extend_from_slice(&buf[..bytes_read]);
+        Ok(bytes_read)
+    }
+}
+
+impl<R: BufRead> BufRead for RecordingReader<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        // the bytes being consumed are still in the buffer, so this does not read anything new
+        if let Ok(buffer) = self.inner.fill_buf() {
+            self.recorded.extend_from_slice(&buffer[..amount.min(buffer.len())]);
+        }
+        self.inner.consume(amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sent_redacts_password() {
+        assert_eq!(TranscriptEntry::sent("PASS secret"), TranscriptEntry::Sent("PASS ***".to_string()));
+        assert_eq!(TranscriptEntry::sent("APOP tim c4c9334bac560ecc979e58001b3e22fb"), TranscriptEntry::Sent("APOP tim ***".to_string()));
+        assert_eq!(TranscriptEntry::sent("AUTH PLAIN AHRpbQB0YW5zdGFhZg=="), TranscriptEntry::Sent("AUTH PLAIN ***".to_string()));
+    }
+
+    #[test]
+    fn test_sent_keeps_other_commands() {
+        assert_eq!(TranscriptEntry::sent("USER tim"), TranscriptEntry::Sent("USER tim".to_string()));
+        assert_eq!(TranscriptEntry::sent("AUTH CRAM-MD5"), TranscriptEntry::Sent("AUTH CRAM-MD5".to_string()));
+        assert_eq!(TranscriptEntry::sent("TOP 1 10"), TranscriptEntry::Sent("TOP 1 10".to_string()));
+    }
+
+    #[test]
+    fn test_recording_reader_records_what_was_read() {
+        let data = b"+OK 2 320\r\n";
+        let mut slice: &[u8] = data.as_ref();
+        let mut reader = RecordingReader::new(&mut slice);
+
+        let mut actual = String::new();
+        reader.read_to_string(&mut actual).unwrap();
+
+        assert_eq!(reader.into_entry(), TranscriptEntry::Received(actual));
+    }
+
+    #[test]
+    fn test_redacted_string_hides_secret() {
+        let secret = RedactedString::new("tanstaaf");
+
+        assert_eq!(format!("{secret:?}"), REDACTED.to_string());
+        assert_eq!(secret.expose(), "tanstaaf");
+        assert_eq!(secret.redact_in("invalid password tanstaaf for tim"), "invalid password *** for tim".to_string());
+        assert_eq!(TranscriptEntry::Received("-ERR tanstaaf\r\n".to_string()).redacted(&secret), TranscriptEntry::Received("-ERR ***\r\n".to_string()));
+    }
+
+    #[test]
+    fn test_recording_reader_records_consumed_lines_only() {
+        let data = b"+OK\r\n+OK next\r\n";
+        let mut slice: &[u8] = data.as_ref();
+        let mut reader = RecordingReader::new(&mut slice);
+
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line).unwrap();
+
+        assert_eq!(reader.into_entry(), TranscriptEntry::Received("+OK\r\n".to_string()));
+    }
+}